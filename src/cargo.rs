@@ -0,0 +1,424 @@
+//! Cargo integration: building the debuggee and locating its executable
+
+use crate::error::ConfigError;
+use crate::{host, server::shell_quote};
+use serde::Deserialize;
+use serde_json::Value;
+use zed_extension_api::{
+    self as zed, DebugRequest, LaunchRequest, Result, TaskTemplate, Worktree,
+};
+
+/// The parts of `cargo metadata` needed to locate build outputs
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Metadata {
+    target_directory: String,
+    packages: Vec<Package>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Package {
+    targets: Vec<Target>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl Metadata {
+    /// Names of all binary targets in the workspace
+    fn binaries(&self) -> Vec<&str> {
+        self.packages
+            .iter()
+            .flat_map(|p| &p.targets)
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    /// Build output directory for `profile`, under the triple's subdirectory when cross-compiling
+    pub(crate) fn profile_dir(&self, profile: &str, target_triple: Option<&str>) -> Option<String> {
+        let profile = match profile {
+            "" => return None,
+            "dev" | "debug" => "debug",
+            other => other,
+        };
+        Some(match target_triple {
+            Some(triple) => format!("{}/{}/{}", self.target_directory, triple, profile),
+            None => format!("{}/{}", self.target_directory, profile),
+        })
+    }
+
+    /// Path of the sole binary target for `profile`, or `None` if there is no binary
+    pub(crate) fn default_program(
+        &self,
+        profile: &str,
+        target_triple: Option<&str>,
+    ) -> Result<Option<String>> {
+        let dir = self
+            .profile_dir(profile, target_triple)
+            .ok_or_else(|| ConfigError::new("profile", "unknown profile")
+                .value(format!("{:?}", profile))
+                .hint("expected \"debug\" or \"release\""))?;
+        match self.binaries().as_slice() {
+            [] => Ok(None),
+            [name] => Ok(Some(format!("{}/{}", dir, name))),
+            names => Err(format!(
+                "`program` is not set and the workspace has several binaries: {} (set `program`, or use a `cargo` block with e.g. \"--bin\", \"{}\")",
+                names.join(", "),
+                names[0]
+            )),
+        }
+    }
+}
+
+/// Run `cargo metadata` for the worktree
+pub(crate) fn metadata(worktree: &Worktree) -> Result<Metadata> {
+    let output = host::run(
+        zed::process::Command::new("cargo")
+            .args(["metadata", "--no-deps", "--format-version", "1", "--manifest-path"])
+            .arg(format!("{}/Cargo.toml", worktree.root_path()))
+            .envs(worktree.shell_env()),
+        "`cargo metadata`",
+    )?;
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("failed to parse `cargo metadata` output: {}", e))
+}
+
+/// An executable reported by a `compiler-artifact` message
+struct Artifact {
+    executable: String,
+    name: String,
+    kind: Vec<String>,
+}
+
+impl Artifact {
+    /// `kind:name` spelling used to pick a test binary via `testKind`
+    fn label(&self) -> String {
+        format!("{}:{}", self.kind.join(","), self.name)
+    }
+}
+
+/// A resolved `cargoTest` block
+pub(crate) struct TestProgram {
+    pub(crate) program: String,
+    /// Arguments for the test harness when launching
+    pub(crate) args: Vec<String>,
+}
+
+/// Run cargo with JSON output in the worktree and return the executables it produced
+fn run_json(
+    args: Vec<String>,
+    worktree: &Worktree,
+    target_triple: Option<&str>,
+) -> Result<Vec<Artifact>> {
+    run_json_in(
+        args,
+        Some(&worktree.root_path()),
+        worktree.shell_env(),
+        target_triple,
+    )
+}
+
+/// Run cargo with JSON output against the manifest in `root`
+fn run_json_in(
+    mut args: Vec<String>,
+    root: Option<&str>,
+    env: Vec<(String, String)>,
+    target_triple: Option<&str>,
+) -> Result<Vec<Artifact>> {
+    if !args.iter().any(|a| a.starts_with("--message-format")) {
+        args.insert(1, "--message-format=json".to_string());
+    }
+    let args = with_build_flags(args, root, target_triple);
+
+    let description = format!("`cargo {}`", args.join(" "));
+    let output = host::run(
+        zed::process::Command::new("cargo").args(args).envs(env),
+        &description,
+    )?;
+    Ok(artifacts(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Add the manifest path and target triple unless `args` already has them.
+/// Flags go right after the subcommand so anything after `--` is left alone.
+fn with_build_flags(
+    mut args: Vec<String>,
+    root: Option<&str>,
+    target_triple: Option<&str>,
+) -> Vec<String> {
+    if let Some(root) = root
+        && !args.iter().any(|a| a.starts_with("--manifest-path"))
+    {
+        args.insert(1, "--manifest-path".to_string());
+        args.insert(2, format!("{}/Cargo.toml", root));
+    }
+    if let Some(triple) = target_triple
+        && !args.iter().any(|a| a == "--target" || a.starts_with("--target="))
+    {
+        args.insert(1, format!("--target={}", triple));
+    }
+    args
+}
+
+/// Environment variable carrying the debuggee's arguments through a locator build task
+const PROGRAM_ARGS_ENV: &str = "LLDB_REMOTE_PROGRAM_ARGS";
+
+/// Turn a runnable `cargo run`/`cargo test` task into the build task of a
+/// debug scenario. Returns `None` for tasks this locator doesn't handle.
+pub(crate) fn locator_build_task(task: &TaskTemplate) -> Option<TaskTemplate> {
+    if task.command != "cargo" {
+        return None;
+    }
+    let (cargo_args, program_args) = match task.args.iter().position(|a| a == "--") {
+        Some(i) => (&task.args[..i], &task.args[i + 1..]),
+        None => (&task.args[..], &[][..]),
+    };
+
+    let (subcommand, rest) = cargo_args.split_first()?;
+    let mut args = match subcommand.as_str() {
+        "run" | "build" => vec!["build".to_string()],
+        "test" => vec!["test".to_string(), "--no-run".to_string()],
+        _ => return None,
+    };
+    args.push("--message-format=json".to_string());
+    args.extend(rest.iter().cloned());
+
+    // `cargo build` rejects trailing arguments, so carry them in the environment
+    let mut env = task.env.clone();
+    if !program_args.is_empty() {
+        env.push((
+            PROGRAM_ARGS_ENV.to_string(),
+            serde_json::to_string(program_args).ok()?,
+        ));
+    }
+
+    Some(TaskTemplate {
+        label: task.label.clone(),
+        command: task.command.clone(),
+        args,
+        env,
+        cwd: task.cwd.clone(),
+    })
+}
+
+/// Second phase of the locator: find the executable the build task produced
+pub(crate) fn locate_program(build_task: TaskTemplate) -> Result<DebugRequest> {
+    let (program_args, env): (Vec<_>, Vec<_>) = build_task
+        .env
+        .into_iter()
+        .partition(|(k, _)| k == PROGRAM_ARGS_ENV);
+    let args = match program_args.first() {
+        Some((_, json)) => serde_json::from_str(json)
+            .map_err(|e| format!("invalid {}: {}", PROGRAM_ARGS_ENV, e))?,
+        None => Vec::new(),
+    };
+
+    // The build already ran, so this only replays cargo's artifact messages
+    let command = build_task.args.join(" ");
+    let executables = run_json_in(
+        build_task.args,
+        build_task.cwd.as_deref(),
+        env.clone(),
+        None,
+    )?;
+    let program = match executables.as_slice() {
+        [artifact] => artifact.executable.clone(),
+        [] => return Err(format!("`cargo {}` produced no executable", command)),
+        _ => {
+            return Err(format!(
+                "`cargo {}` produced several executables: {}",
+                command,
+                executables
+                    .iter()
+                    .map(Artifact::label)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    };
+
+    Ok(DebugRequest::Launch(LaunchRequest {
+        program,
+        cwd: build_task.cwd,
+        args,
+        envs: env,
+    }))
+}
+
+/// The `args` of a `cargo` block
+fn build_args(cargo: &Value) -> Result<Vec<String>> {
+    let args = cargo
+        .get("args")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| ConfigError::new("cargo.args", "is missing")
+            .hint("expected e.g. [\"build\", \"--bin\", \"app\"]"))?
+        .iter()
+        .map(|a| {
+            a.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| ConfigError::new("cargo.args", "has an entry that is not a string")
+                    .value(a)
+                    .to_string())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if args.is_empty() {
+        return Err(ConfigError::new("cargo.args", "is empty")
+            .hint("expected a subcommand such as \"build\"")
+            .into());
+    }
+    Ok(args)
+}
+
+/// Run the `cargo` block of the config and return the executable it produced
+pub(crate) fn build_program(
+    cargo: &Value,
+    worktree: &Worktree,
+    target_triple: Option<&str>,
+) -> Result<String> {
+    let args = build_args(cargo)?;
+    let command = args.join(" ");
+
+    let executables = run_json(args, worktree, target_triple)?;
+    match executables.as_slice() {
+        [artifact] => Ok(artifact.executable.clone()),
+        [] => Err(format!(
+            "`cargo {}` produced no executable (use --bin, --example or --test to select one)",
+            command
+        )),
+        _ => Err(format!(
+            "`cargo {}` produced {} executables, expected one:\n  {}",
+            command,
+            executables.len(),
+            executables
+                .iter()
+                .map(|a| a.executable.as_str())
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        )),
+    }
+}
+
+/// A string field of the `cargoTest` block
+fn test_field<'a>(cargo_test: &'a Value, key: &str) -> Result<Option<&'a str>> {
+    match cargo_test.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.as_str())),
+        Some(v) => Err(ConfigError::new(format!("cargoTest.{}", key), "not a string").value(v).into()),
+    }
+}
+
+/// The cargo arguments that build the tests of the `cargoTest` block
+fn test_build_args(cargo_test: &Value) -> Result<Vec<String>> {
+    let mut args = vec!["test".to_string(), "--no-run".to_string()];
+    if let Some(package) = test_field(cargo_test, "package")? {
+        args.push("--package".to_string());
+        args.push(package.to_string());
+    }
+    Ok(args)
+}
+
+/// Shell command that repeats the build of the `cargo` or `cargoTest` block,
+/// for a restart to run on its own
+pub(crate) fn rebuild_command(
+    cfg_in: &Value,
+    worktree_root: &str,
+    target_triple: Option<&str>,
+) -> Result<Option<String>> {
+    let args = if let Some(cargo) = cfg_in.get("cargo") {
+        build_args(cargo)?
+    } else if let Some(cargo_test) = cfg_in.get("cargoTest") {
+        test_build_args(cargo_test)?
+    } else {
+        return Ok(None);
+    };
+    let args = with_build_flags(args, Some(worktree_root), target_triple);
+    Ok(Some(format!(
+        "cargo {}",
+        args.iter()
+            .map(|a| shell_quote(a))
+            .collect::<Vec<_>>()
+            .join(" ")
+    )))
+}
+
+/// Build the tests of the `cargoTest` block and pick the test binary to debug
+pub(crate) fn build_test(
+    cargo_test: &Value,
+    worktree: &Worktree,
+    target_triple: Option<&str>,
+) -> Result<TestProgram> {
+    let filter = test_field(cargo_test, "filter")?;
+    let test_kind = test_field(cargo_test, "testKind")?;
+    let args = test_build_args(cargo_test)?;
+
+    // `testKind` is either a target kind (`lib`, `bin`, `test`) or `kind:name`
+    let candidates: Vec<Artifact> = run_json(args, worktree, target_triple)?
+        .into_iter()
+        .filter(|a| match test_kind {
+            None => true,
+            Some(kind) => match kind.split_once(':') {
+                Some((kind, name)) => a.kind.iter().any(|k| k == kind) && a.name == name,
+                None => a.kind.iter().any(|k| k == kind),
+            },
+        })
+        .collect();
+
+    let artifact = match candidates.as_slice() {
+        [artifact] => artifact,
+        [] => {
+            return Err(format!(
+                "`cargoTest` produced no matching test binary{}",
+                test_kind
+                    .map(|k| format!(" for `testKind` {:?}", k))
+                    .unwrap_or_default()
+            ));
+        }
+        _ => {
+            return Err(format!(
+                "`cargoTest` produced several test binaries; set `testKind` to one of: {}",
+                candidates
+                    .iter()
+                    .map(Artifact::label)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    };
+
+    let args = match filter {
+        Some(filter) => vec![
+            filter.to_string(),
+            "--exact".to_string(),
+            "--nocapture".to_string(),
+        ],
+        None => vec!["--nocapture".to_string()],
+    };
+    Ok(TestProgram {
+        program: artifact.executable.clone(),
+        args,
+    })
+}
+
+/// Collect the executables from cargo's `--message-format=json` output
+fn artifacts(stdout: &str) -> Vec<Artifact> {
+    stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg.get("reason").and_then(|v| v.as_str()) == Some("compiler-artifact"))
+        .filter_map(|msg| {
+            let target = msg.get("target")?;
+            Some(Artifact {
+                executable: msg.get("executable")?.as_str()?.to_string(),
+                name: target.get("name")?.as_str()?.to_string(),
+                kind: target
+                    .get("kind")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|k| k.as_str().map(str::to_string))
+                    .collect(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+//! Building LLDB command lines
+
+use std::fmt::{Display, Write};
+
+/// An LLDB command line. Words such as options and numbers are added as they
+/// are; arguments that may hold spaces, quotes or backslashes (paths, names)
+/// are quoted for LLDB's parser.
+pub(crate) struct CommandBuilder {
+    line: String,
+}
+
+impl CommandBuilder {
+    /// Start a command, e.g. `target create`
+    pub(crate) fn new(command: &str) -> Self {
+        Self {
+            line: command.to_string(),
+        }
+    }
+
+    /// Add a word that needs no quoting
+    pub(crate) fn word(mut self, word: impl Display) -> Self {
+        let _ = write!(self.line, " {}", word);
+        self
+    }
+
+    /// Add an argument, quoted
+    pub(crate) fn arg(mut self, arg: &str) -> Self {
+        self.line.push(' ');
+        self.line.push_str(&quote(arg));
+        self
+    }
+
+    pub(crate) fn build(self) -> String {
+        self.line
+    }
+}
+
+/// Quote a string as one LLDB command argument
+pub(crate) fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split a command line into words the way LLDB reads it: whitespace separates
+/// words, `'...'` is taken as written, and inside `"..."` a `\` escapes `"` and
+/// `\`. Outside quotes a `\` escapes a quote, a `\` or a space.
+pub(crate) fn split(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            words.extend(word.take());
+            continue;
+        }
+        let word = word.get_or_insert_with(String::new);
+        match c {
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\')) => word.extend(chars.next()),
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\'' => word.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '\\' if chars.peek().is_some_and(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\')) => {
+                word.extend(chars.next())
+            }
+            c => word.push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// Commands that take the rest of the line as it is written, e.g. as Python or
+/// an expression, where quoting can't be rewritten
+const RAW_INPUT_COMMANDS: [&str; 8] = ["script", "expression", "expr", "e", "p", "print", "po", "call"];
+
+/// `line` in one spelling for all the ways of writing it LLDB reads the same:
+/// every word quoted, separated by single spaces. After raw-input commands
+/// such as `script` only the spacing around the command name is evened out.
+pub(crate) fn normalize(line: &str) -> String {
+    let line = line.trim();
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if RAW_INPUT_COMMANDS.contains(&command) {
+        return format!("{} {}", command, rest.trim_start());
+    }
+    split(line).iter().map(|w| quote(w)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_undoes_quote() {
+        for arg in ["plain", "/home/john/My Project/src", r#"say "hi""#, r"C:\Users\me", ""] {
+            let line = CommandBuilder::new("settings set").arg(arg).build();
+            assert_eq!(split(&line), ["settings", "set", arg]);
+        }
+    }
+
+    #[test]
+    fn split_reads_lldb_quoting() {
+        assert_eq!(
+            split(r#"  settings   append target.source-map '/a b' "/c\"d" /e\ f C:\src "#),
+            ["settings", "append", "target.source-map", "/a b", "/c\"d", "/e f", r"C:\src"]
+        );
+        assert_eq!(split(r#"x "" ''"#), ["x", "", ""]);
+    }
+
+    #[test]
+    fn normalize_matches_differently_quoted_lines() {
+        assert_eq!(
+            normalize(r#"settings set target.source-map "/a b" /c"#),
+            normalize("  settings  set target.source-map '/a b'  \"/c\"")
+        );
+        assert_ne!(normalize("settings set x a b"), normalize("settings set x 'a b'"));
+        assert_eq!(normalize("script  print('a')"), normalize("script print('a') "));
+        assert_ne!(normalize("script print('a')"), normalize(r#"script print("a")"#));
+    }
+
+    #[test]
+    fn arguments_are_quoted_and_words_are_not() {
+        let cases = [
+            ("/home/alice/my project/binary", r#"target create "/home/alice/my project/binary""#),
+            (r#"/tmp/say "hi"/app"#, r#"target create "/tmp/say \"hi\"/app""#),
+            (r"C:\Users\me\app.exe", r#"target create "C:\\Users\\me\\app.exe""#),
+            (r#"C:\"quoted\""#, r#"target create "C:\\\"quoted\\\"""#),
+        ];
+        for (program, line) in cases {
+            assert_eq!(CommandBuilder::new("target create").arg(program).build(), line);
+        }
+        assert_eq!(
+            CommandBuilder::new("target create").word("--arch").arg("arm64").word(7).build(),
+            r#"target create --arch "arm64" 7"#
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! The fields of a configuration the extension reads everywhere, parsed once
+//! with their defaults
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use zed_extension_api::Result;
+
+use crate::error::ConfigError;
+
+/// The config's switches, where to connect, what to debug and the commands to
+/// run. Fields other than these are read where they are used; a `null` field
+/// means its default.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct RemoteDebugConfig {
+    pub(crate) allow_shell_expansion: bool,
+    pub(crate) check_lldb_dap_version: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) re_attach: bool,
+    pub(crate) extended_remote: bool,
+    pub(crate) verify_connection: bool,
+    pub(crate) stop_on_entry: bool,
+    pub(crate) stop_on_entry_break_main: bool,
+    pub(crate) disable_stdio_redirect: bool,
+    pub(crate) validate_path_mappings: bool,
+    pub(crate) strict_paths: bool,
+    pub(crate) reverse_source_map: bool,
+    pub(crate) verbose: bool,
+    pub(crate) dedup_init_commands: bool,
+    pub(crate) human_readable_config: bool,
+    pub(crate) cache_config: bool,
+    pub(crate) lenient_commands: bool,
+    pub(crate) custom_request_args_override: bool,
+
+    /// `tcp://HOST:PORT` addresses, one per core
+    pub(crate) target: Option<OneOrMany<String>>,
+    /// The same as a single `target`, spelled out
+    pub(crate) connection: Option<Connection>,
+    /// Local binary, or candidates for it of which the first that exists is used
+    pub(crate) program: Option<OneOrMany<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) init_commands: Option<Vec<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) attach_commands: Option<Vec<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) launch_commands: Option<Vec<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) pre_run_commands: Option<Vec<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) post_run_commands: Option<Vec<String>>,
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) exit_commands: Option<Vec<String>>,
+    /// Monitor packets for the stub, sent once connected
+    #[serde(deserialize_with = "command_lines")]
+    pub(crate) monitor_commands: Option<Vec<String>>,
+    pub(crate) path_mappings: Option<Vec<PathMappingEntry>>,
+    /// lldb-dap's environment, under its deprecated name
+    pub(crate) env: Option<Map<String, Value>>,
+    pub(crate) adapter_env: Option<Map<String, Value>>,
+}
+
+/// A field that takes one value or an array of them
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// The `connection` object
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Connection {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// `tcp` (the default) or `tcps`
+    pub(crate) protocol: Option<String>,
+}
+
+/// One `pathMappings` entry as written, before variable expansion
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PathMappingEntry {
+    pub(crate) local_root: String,
+    pub(crate) remote_root: String,
+    /// Map `localRoot` to `remoteRoot` instead
+    pub(crate) reverse_source_map: Option<bool>,
+}
+
+impl Default for RemoteDebugConfig {
+    fn default() -> Self {
+        Self {
+            allow_shell_expansion: false,
+            check_lldb_dap_version: false,
+            dry_run: false,
+            re_attach: false,
+            extended_remote: false,
+            verify_connection: false,
+            stop_on_entry: false,
+            stop_on_entry_break_main: false,
+            disable_stdio_redirect: false,
+            validate_path_mappings: false,
+            strict_paths: false,
+            reverse_source_map: false,
+            verbose: false,
+            dedup_init_commands: true,
+            human_readable_config: false,
+            cache_config: false,
+            lenient_commands: false,
+            custom_request_args_override: false,
+            target: None,
+            connection: None,
+            program: None,
+            init_commands: None,
+            attach_commands: None,
+            launch_commands: None,
+            pre_run_commands: None,
+            post_run_commands: None,
+            exit_commands: None,
+            monitor_commands: None,
+            path_mappings: None,
+            env: None,
+            adapter_env: None,
+        }
+    }
+}
+
+/// Config keys holding LLDB commands the user wrote, which take placeholders
+pub(crate) const USER_COMMAND_KEYS: [&str; 6] = [
+    "initCommands",
+    "attachCommands",
+    "launchCommands",
+    "preRunCommands",
+    "postRunCommands",
+    "exitCommands",
+];
+
+impl RemoteDebugConfig {
+    pub(crate) fn parse(cfg_in: &Value) -> Result<Self> {
+        let Some(obj) = cfg_in.as_object() else {
+            return Ok(Self::default());
+        };
+        let mut set: Map<String, Value> = obj
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        // Fields whose entries are checked here, to say which entry is wrong
+        let lenient = set.get("lenientCommands") == Some(&Value::Bool(true));
+        for key in USER_COMMAND_KEYS {
+            check_commands(&mut set, key, lenient)?;
+        }
+        check_commands(&mut set, "monitorCommands", lenient)?;
+        check_string_list(&set, "target", "expected tcp://HOST:PORT")?;
+        check_string_list(&set, "program", "expected a path")?;
+        check_connection(&set)?;
+        check_path_mappings(&set)?;
+
+        serde_json::from_value(Value::Object(set.clone())).map_err(|e| {
+            // serde doesn't say which field it choked on, so try them one at a time
+            set.iter()
+                .find(|(k, v)| serde_json::from_value::<Self>(serde_json::json!({ *k: v })).is_err())
+                .map(|(k, v)| ConfigError::new(k.as_str(), problem(k)).value(v).to_string())
+                .unwrap_or_else(|| format!("invalid debug configuration: {}", e))
+        })
+    }
+
+    /// The commands under `key`, one of `USER_COMMAND_KEYS`
+    pub(crate) fn commands(&self, key: &str) -> &[String] {
+        let commands = match key {
+            "initCommands" => &self.init_commands,
+            "attachCommands" => &self.attach_commands,
+            "launchCommands" => &self.launch_commands,
+            "preRunCommands" => &self.pre_run_commands,
+            "postRunCommands" => &self.post_run_commands,
+            "exitCommands" => &self.exit_commands,
+            _ => &None,
+        };
+        commands.as_deref().unwrap_or_default()
+    }
+}
+
+/// What is wrong with a field that failed to parse
+fn problem(key: &str) -> &'static str {
+    match key {
+        "env" | "adapterEnv" => "not an object of environment variables",
+        _ => "not a boolean",
+    }
+}
+
+/// An array of command lines, or a multi-line block pasted as one string,
+/// split into trimmed, non-empty lines
+fn command_lines<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Vec<String>>, D::Error> {
+    Ok(Some(match OneOrMany::<String>::deserialize(deserializer)? {
+        OneOrMany::One(block) => block
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        OneOrMany::Many(lines) => lines,
+    }))
+}
+
+/// Entries of a command array that aren't strings fail the session, unless
+/// `lenient` (`lenientCommands`) drops them; so is a value that isn't a list
+fn check_commands(set: &mut Map<String, Value>, key: &str, lenient: bool) -> std::result::Result<(), ConfigError> {
+    match set.get_mut(key) {
+        None | Some(Value::String(_)) => {}
+        Some(Value::Array(cmds)) => {
+            let invalid: Vec<String> = cmds
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| !c.is_string())
+                .map(|(i, c)| format!("[{}] is {}", i, json_type(c)))
+                .collect();
+            if !invalid.is_empty() && !lenient {
+                return Err(ConfigError::new(key, "has entries that are not strings")
+                    .value(invalid.join(", "))
+                    .hint("expected LLDB commands as strings; `\"lenientCommands\": true` skips the others"));
+            }
+            cmds.retain(Value::is_string);
+        }
+        Some(_) if lenient => {
+            set.remove(key);
+        }
+        Some(v) => {
+            return Err(ConfigError::new(key, "not a list of commands")
+                .value(&*v)
+                .hint("expected an array of strings, or one string with a command per line"));
+        }
+    }
+    Ok(())
+}
+
+/// A string-or-array field: name the array entry that isn't a string
+fn check_string_list(set: &Map<String, Value>, key: &str, hint: &str) -> std::result::Result<(), ConfigError> {
+    match set.get(key) {
+        None | Some(Value::String(_)) => Ok(()),
+        Some(Value::Array(entries)) => match entries.iter().position(|e| !e.is_string()) {
+            Some(i) => Err(ConfigError::new(format!("{}[{}]", key, i), "not a string")
+                .value(&entries[i])
+                .hint(hint)),
+            None => Ok(()),
+        },
+        Some(v) => Err(ConfigError::new(key, "not a string or an array").value(v).hint(hint)),
+    }
+}
+
+fn check_connection(set: &Map<String, Value>) -> std::result::Result<(), ConfigError> {
+    let Some(connection) = set.get("connection") else {
+        return Ok(());
+    };
+    let obj = connection.as_object().ok_or_else(|| {
+        ConfigError::new("connection", "not an object")
+            .value(connection)
+            .hint("expected {\"host\": ..., \"port\": ...}")
+    })?;
+    match obj.get("host") {
+        Some(Value::String(h)) if !h.is_empty() => {}
+        Some(v) => return Err(ConfigError::new("connection.host", "not a hostname").value(v).hint("expected a hostname")),
+        None => return Err(ConfigError::new("connection.host", "is missing").hint("expected a hostname")),
+    }
+    match obj.get("port") {
+        Some(v) if v.as_u64().and_then(|p| u16::try_from(p).ok()).is_some_and(|p| p > 0) => {}
+        Some(v) => {
+            return Err(ConfigError::new("connection.port", "not a port number")
+                .value(v)
+                .hint("expected an integer from 1 to 65535"));
+        }
+        None => return Err(ConfigError::new("connection.port", "is missing").hint("expected an integer from 1 to 65535")),
+    }
+    match obj.get("protocol") {
+        None | Some(Value::Null) => Ok(()),
+        Some(Value::String(p)) if p == "tcp" || p == "tcps" => Ok(()),
+        Some(v) => Err(ConfigError::new("connection.protocol", "unsupported protocol")
+            .value(v)
+            .hint("expected \"tcp\" or \"tcps\"")),
+    }
+}
+
+/// Check that every `pathMappings` entry has both roots, rather than skipping
+/// the ones that don't
+fn check_path_mappings(set: &Map<String, Value>) -> std::result::Result<(), ConfigError> {
+    let mappings = match set.get("pathMappings") {
+        None => return Ok(()),
+        Some(Value::Array(mappings)) => mappings,
+        Some(v) => {
+            return Err(ConfigError::new("pathMappings", "not an array")
+                .value(v)
+                .hint("expected [{\"localRoot\": ..., \"remoteRoot\": ...}]"));
+        }
+    };
+    for (i, mapping) in mappings.iter().enumerate() {
+        if !mapping.is_object() {
+            return Err(ConfigError::new(format!("pathMappings[{}]", i), "not an object")
+                .value(mapping)
+                .hint("expected {\"localRoot\": ..., \"remoteRoot\": ...}"));
+        }
+        for field in ["localRoot", "remoteRoot"] {
+            let key = format!("pathMappings[{}].{}", i, field);
+            match mapping.get(field) {
+                Some(Value::String(root)) if !root.is_empty() => {}
+                Some(v) => return Err(ConfigError::new(key, "not a path").value(v).hint("expected a directory")),
+                None => return Err(ConfigError::new(key, "is missing").hint("expected a directory")),
+            }
+        }
+        match mapping.get("reverseSourceMap") {
+            None | Some(Value::Null | Value::Bool(_)) => {}
+            Some(v) => {
+                return Err(ConfigError::new(format!("pathMappings[{}].reverseSourceMap", i), "not a boolean")
+                    .value(v)
+                    .hint("expected true or false"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The JSON type of `value`, for error messages
+pub(crate) fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn error(cfg_in: Value) -> String {
+        RemoteDebugConfig::parse(&cfg_in).unwrap_err()
+    }
+
+    #[test]
+    fn parses_the_typed_fields() {
+        let flags = RemoteDebugConfig::parse(&json!({
+            "target": ["tcp://board:1234", "tcp://board:1235"],
+            "connection": { "host": "board", "port": 1234 },
+            "program": "build/app",
+            "initCommands": "settings set a 1\n\n  settings set b 2  \n",
+            "attachCommands": ["gdb-remote board:1234"],
+            "pathMappings": [{ "localRoot": "/src", "remoteRoot": "/build", "reverseSourceMap": true }],
+            "env": { "A": "1" },
+        }))
+        .unwrap();
+        assert_eq!(
+            flags.target,
+            Some(OneOrMany::Many(vec!["tcp://board:1234".into(), "tcp://board:1235".into()]))
+        );
+        assert_eq!(
+            flags.connection,
+            Some(Connection { host: "board".into(), port: 1234, protocol: None })
+        );
+        assert_eq!(flags.program, Some(OneOrMany::One("build/app".into())));
+        assert_eq!(flags.commands("initCommands"), ["settings set a 1", "settings set b 2"]);
+        assert_eq!(flags.commands("attachCommands"), ["gdb-remote board:1234"]);
+        assert!(flags.commands("launchCommands").is_empty());
+        assert!(flags.launch_commands.is_none());
+        assert_eq!(
+            flags.path_mappings,
+            Some(vec![PathMappingEntry {
+                local_root: "/src".into(),
+                remote_root: "/build".into(),
+                reverse_source_map: Some(true),
+            }])
+        );
+        assert_eq!(flags.env.unwrap()["A"], "1");
+    }
+
+    #[test]
+    fn null_fields_take_their_defaults() {
+        let flags = RemoteDebugConfig::parse(&json!({ "dedupInitCommands": null, "target": null })).unwrap();
+        assert!(flags.dedup_init_commands);
+        assert!(flags.target.is_none());
+    }
+
+    #[test]
+    fn commands_that_are_not_strings_fail_unless_lenient() {
+        assert_eq!(
+            error(json!({ "initCommands": ["bt", 1, null] })),
+            "`initCommands`: has entries that are not strings\n  value: [1] is a number, [2] is null\n  \
+             hint: expected LLDB commands as strings; `\"lenientCommands\": true` skips the others"
+        );
+        let flags = RemoteDebugConfig::parse(&json!({
+            "lenientCommands": true,
+            "initCommands": ["bt", 1],
+            "postRunCommands": { "not": "a list" },
+        }))
+        .unwrap();
+        assert_eq!(flags.commands("initCommands"), ["bt"]);
+        assert!(flags.post_run_commands.is_none());
+    }
+
+    #[test]
+    fn names_the_target_entry_that_is_not_a_string() {
+        assert_eq!(
+            error(json!({ "target": ["tcp://board:1234", 1235] })),
+            "`target[1]`: not a string\n  value: 1235\n  hint: expected tcp://HOST:PORT"
+        );
+        assert_eq!(
+            error(json!({ "program": {} })),
+            "`program`: not a string or an array\n  value: {}\n  hint: expected a path"
+        );
+    }
+
+    #[test]
+    fn checks_the_connection_fields() {
+        assert_eq!(
+            error(json!({ "connection": { "port": 1234 } })),
+            "`connection.host`: is missing\n  hint: expected a hostname"
+        );
+        assert_eq!(
+            error(json!({ "connection": { "host": "board", "port": 70000 } })),
+            "`connection.port`: not a port number\n  value: 70000\n  hint: expected an integer from 1 to 65535"
+        );
+        assert_eq!(
+            error(json!({ "connection": { "host": "board", "port": 1234, "protocol": "udp" } })),
+            "`connection.protocol`: unsupported protocol\n  value: \"udp\"\n  hint: expected \"tcp\" or \"tcps\""
+        );
+    }
+
+    #[test]
+    fn checks_the_path_mapping_entries() {
+        assert_eq!(
+            error(json!({ "pathMappings": [{ "localRoot": "/src" }] })),
+            "`pathMappings[0].remoteRoot`: is missing\n  hint: expected a directory"
+        );
+        assert_eq!(
+            error(json!({ "pathMappings": [{ "localRoot": "/src", "remoteRoot": "/b", "reverseSourceMap": "yes" }] })),
+            "`pathMappings[0].reverseSourceMap`: not a boolean\n  value: \"yes\"\n  hint: expected true or false"
+        );
+    }
+
+    #[test]
+    fn names_the_switch_that_is_not_a_boolean() {
+        assert_eq!(error(json!({ "verbose": "yes" })), "`verbose`: not a boolean\n  value: \"yes\"");
+        assert_eq!(
+            error(json!({ "env": ["A=1"] })),
+            "`env`: not an object of environment variables\n  value: [\"A=1\"]"
+        );
+    }
+
+    #[test]
+    fn a_command_block_reads_like_the_array_of_its_lines() {
+        let block = RemoteDebugConfig::parse(&json!({
+            "attachCommands": "  gdb-remote board:1234\n\n  process status  \r\n",
+            "initCommands": "settings set target.language rust",
+        }))
+        .unwrap();
+        let array = RemoteDebugConfig::parse(&json!({
+            "attachCommands": ["gdb-remote board:1234", "process status"],
+            "initCommands": ["settings set target.language rust"],
+        }))
+        .unwrap();
+        assert_eq!(block.commands("attachCommands"), ["gdb-remote board:1234", "process status"]);
+        assert_eq!(block.commands("attachCommands"), array.commands("attachCommands"));
+        assert_eq!(block.commands("initCommands"), array.commands("initCommands"));
+        assert!(block.commands("preRunCommands").is_empty());
+    }
+}
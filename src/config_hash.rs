@@ -0,0 +1,62 @@
+//! Fingerprints for telling whether two configurations are the same
+
+use std::fmt;
+
+use serde_json::Value;
+use zed_extension_api::Result;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of a configuration's canonical JSON
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Fingerprint(u64);
+
+impl Fingerprint {
+    /// Fingerprint a parsed configuration. `serde_json` keeps object keys
+    /// sorted, so the serialized form is canonical.
+    pub(crate) fn of(config: &Value) -> Self {
+        Self(fnv1a(config.to_string().as_bytes()))
+    }
+
+    /// Fingerprint a configuration given as JSON text, ignoring formatting
+    pub(crate) fn of_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map(|config| Self::of(&config))
+            .map_err(|e| format!("invalid debug configuration JSON: {}", e))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_fnv1a_reference_vectors() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+        assert_eq!(Fingerprint(0xaf).to_string(), "00000000000000af");
+    }
+
+    #[test]
+    fn ignores_key_order_and_formatting() {
+        let a = Fingerprint::of_json(r#"{"target": "tcp://board:1234", "program": "app"}"#).unwrap();
+        let b = Fingerprint::of_json("{\n  \"program\": \"app\",\n  \"target\": \"tcp://board:1234\"\n}").unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, Fingerprint::of_json(r#"{"target": "tcp://board:1235", "program": "app"}"#).unwrap());
+        assert!(Fingerprint::of_json("{").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Copying the program to the target and restarting its debug server
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+use zed_extension_api::{Result, Worktree, process::Command};
+
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables, host, server::shell_quote};
+
+/// Run the `deploy` block before the adapter is spawned.
+/// `deployed` maps `(program, copyTo)` to the checksum last copied there.
+pub(crate) fn run(
+    deploy: &Value,
+    program: Option<&str>,
+    vars: &Variables,
+    worktree: &Worktree,
+    deployed: &mut HashMap<(String, String), String>,
+) -> Result<()> {
+    let copy_to = field(deploy, "copyTo", vars)?;
+    let restart = field(deploy, "restartCommand", vars)?;
+    let skip_if_unchanged = deploy
+        .get("skipIfUnchanged")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let env = worktree.shell_env();
+
+    if let Some(copy_to) = copy_to {
+        let program = program
+            .ok_or_else(|| "`deploy.copyTo` needs a `program` to copy".to_string())?;
+        let key = (program.to_string(), copy_to.clone());
+
+        let checksum = if skip_if_unchanged {
+            Some(checksum(program, &env)?)
+        } else {
+            None
+        };
+        let unchanged = checksum.is_some() && deployed.get(&key) == checksum.as_ref();
+        if !unchanged {
+            host::run(
+                Command::new("scp")
+                    .args([program, copy_to.as_str()])
+                    .envs(env.clone()),
+                &format!("`scp {} {}`", program, copy_to),
+            )?;
+            match checksum {
+                Some(checksum) => deployed.insert(key, checksum),
+                None => deployed.remove(&key),
+            };
+        }
+    }
+
+    if let Some(restart) = restart {
+        host::run(
+            Command::new("sh").args(["-c", restart.as_str()]).envs(env),
+            &format!("`{}`", restart),
+        )?;
+    }
+    Ok(())
+}
+
+/// Shell commands that repeat the `deploy` block, for a restart to run on its
+/// own. The copy is unconditional, since there is no earlier checksum to compare.
+pub(crate) fn shell_commands(
+    deploy: &Value,
+    program: Option<&str>,
+    vars: &Variables,
+) -> Result<Vec<String>> {
+    let mut commands = Vec::new();
+    if let Some(copy_to) = field(deploy, "copyTo", vars)? {
+        let program = program
+            .ok_or_else(|| "`deploy.copyTo` needs a `program` to copy".to_string())?;
+        commands.push(format!("scp {} {}", shell_quote(program), shell_quote(&copy_to)));
+    }
+    commands.extend(field(deploy, "restartCommand", vars)?);
+    Ok(commands)
+}
+
+/// A string field of the `deploy` block, with variables expanded
+fn field(deploy: &Value, key: &str, vars: &Variables) -> Result<Option<String>> {
+    match deploy.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(expand_variables(s, vars))),
+        Some(v) => Err(ConfigError::new(format!("deploy.{}", key), "not a string").value(v).into()),
+    }
+}
+
+/// Checksum of a local file, via `cksum` since the extension can't read binaries itself
+fn checksum(path: &str, env: &[(String, String)]) -> Result<String> {
+    let output = host::run(
+        Command::new("cksum").arg(path).envs(env.to_vec()),
+        &format!("`cksum {}`", path),
+    )?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // `CRC SIZE PATH`; the path itself is not part of the content
+    Ok(stdout.split_whitespace().take(2).collect::<Vec<_>>().join(" "))
+}
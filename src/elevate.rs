@@ -0,0 +1,84 @@
+//! Running the debug server or the adapter as root (`elevate`)
+
+use serde_json::Value;
+use zed_extension_api::{self as zed, Result, Worktree, process::Command};
+
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables};
+
+/// Elevation command used when `elevateCommand` is not set
+const DEFAULT_ELEVATE_COMMAND: &str = "sudo";
+
+/// The command words to put in front of whatever runs as root, or `None`
+/// without `"elevate": true`. A password prompt nobody can see would hang
+/// the session, so this fails unless the prompt can be answered.
+pub(crate) fn prefix(
+    cfg_in: &Value,
+    worktree: &Worktree,
+    vars: &Variables,
+    has_server: bool,
+) -> Result<Option<Vec<String>>> {
+    if cfg_in.get("elevate").and_then(|v| v.as_bool()) != Some(true) {
+        return Ok(None);
+    }
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Err("`elevate` is not supported on Windows".to_string());
+    }
+    let command = match cfg_in.get("elevateCommand") {
+        None | Some(Value::Null) => DEFAULT_ELEVATE_COMMAND.to_string(),
+        Some(Value::String(c)) if !c.is_empty() => expand_variables(c, vars),
+        Some(v) => return Err(ConfigError::new("elevateCommand", "not a command").value(v).into()),
+    };
+    if worktree.which(&command).is_none() && !command.contains('/') {
+        return Err(format!("`elevateCommand` {} not found in PATH", command));
+    }
+    let env = |name: &str| vars.shell_env.iter().any(|(k, v)| k == name && !v.is_empty());
+
+    match command.rsplit('/').next().unwrap_or(&command) {
+        "sudo" => {
+            // Cached credentials or NOPASSWD need no prompt at all
+            let no_password = Command::new("sudo")
+                .args(["-n", "true"])
+                .output()
+                .is_ok_and(|o| o.status == Some(0));
+            if no_password {
+                Ok(Some(vec![command, "-n".to_string()]))
+            } else if env("SUDO_ASKPASS") {
+                Ok(Some(vec![command, "-A".to_string()]))
+            } else {
+                Err("`elevate` needs sudo to ask for the password without a terminal: set SUDO_ASKPASS to a graphical askpass program (e.g. ssh-askpass), or run `sudo -v` in a terminal first".to_string())
+            }
+        }
+        "pkexec" => {
+            // pkexec replaces itself with the program, which then can't be
+            // signalled by the unprivileged wrapper that has to stop it
+            if has_server {
+                return Err("`elevateCommand` pkexec can't run a debug server the session could stop again; use sudo".to_string());
+            }
+            if !env("DISPLAY") && !env("WAYLAND_DISPLAY") {
+                return Err("`elevate` with pkexec needs a graphical session with a polkit agent to ask for the password (neither DISPLAY nor WAYLAND_DISPLAY is set)".to_string());
+            }
+            Ok(Some(vec![command]))
+        }
+        // Anything else is trusted to prompt on its own
+        _ => Ok(Some(vec![command])),
+    }
+}
+
+/// The command and arguments that run the adapter as root. The elevation
+/// command drops the environment, so `envs` are passed on through `env`.
+pub(crate) fn wrap_adapter(
+    prefix: &[String],
+    adapter: String,
+    adapter_args: Vec<String>,
+    envs: &[(String, String)],
+) -> (String, Vec<String>) {
+    let mut arguments: Vec<String> = prefix[1..].to_vec();
+    if !envs.is_empty() {
+        arguments.push("env".to_string());
+        arguments.extend(envs.iter().map(|(k, v)| format!("{}={}", k, v)));
+    }
+    arguments.push(adapter);
+    arguments.extend(adapter_args);
+    (prefix[0].clone(), arguments)
+}
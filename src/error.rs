@@ -0,0 +1,87 @@
+//! Config errors that name the field, show what it held and say what was expected
+
+use std::fmt;
+
+/// A problem with one field of the config. `?` turns it into the extension
+/// API's `String` error, so it can be returned wherever a `Result` is.
+#[derive(Debug)]
+pub(crate) struct ConfigError {
+    /// Where the field is, e.g. `target[1]` or `pathMappings[0].localRoot`
+    key: String,
+    problem: String,
+    /// The offending value as written in the config
+    value: Option<String>,
+    /// What would have been accepted, or what to do about it
+    hint: Option<String>,
+}
+
+impl ConfigError {
+    pub(crate) fn new(key: impl Into<String>, problem: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            problem: problem.into(),
+            value: None,
+            hint: None,
+        }
+    }
+
+    /// Show `value`: JSON values display as JSON, strings should come quoted
+    pub(crate) fn value(mut self, value: impl fmt::Display) -> Self {
+        self.value = Some(value.to_string());
+        self
+    }
+
+    pub(crate) fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// The same problem, reported for the field at `key`, for values that are
+    /// parsed before it is known which field they came from
+    pub(crate) fn at(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.key, self.problem)?;
+        if let Some(value) = &self.value {
+            write!(f, "\n  value: {}", value)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ConfigError> for String {
+    fn from(error: ConfigError) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_the_key_then_the_value_and_hint_on_their_own_lines() {
+        let error = ConfigError::new("pathMappings[0].localRoot", "not a path")
+            .value(serde_json::json!(3))
+            .hint("expected a directory");
+        assert_eq!(
+            String::from(error),
+            "`pathMappings[0].localRoot`: not a path\n  value: 3\n  hint: expected a directory"
+        );
+        assert_eq!(ConfigError::new("target", "is missing").to_string(), "`target`: is missing");
+    }
+
+    #[test]
+    fn at_moves_the_error_to_another_key() {
+        let error = ConfigError::new("target", "not a HOST:PORT address").value("\"board\"").at("target[1]");
+        assert_eq!(error.to_string(), "`target[1]`: not a HOST:PORT address\n  value: \"board\"");
+    }
+}
@@ -0,0 +1,196 @@
+//! Configurations that inherit from another one (`"extends": "name"`)
+
+use serde_json::Value;
+use zed_extension_api::{Result, Worktree};
+
+use crate::error::ConfigError;
+use crate::merge_json;
+
+/// Zed's debug configurations, looked up by `label`
+const DEBUG_JSON: &str = ".zed/debug.json";
+/// Named base configurations that aren't sessions of their own
+const DEFAULTS_FILE: &str = ".zed/lldb-remote-defaults.json";
+
+/// Scenario fields that describe the entry rather than the session. `request`
+/// is settled before the config reaches the extension, so it isn't inherited.
+const NOT_INHERITED: [&str; 5] = ["label", "adapter", "build", "tcp_connection", "request"];
+
+/// Merge the chain of configurations `cfg_in` extends under it, nearest last.
+/// `label` names `cfg_in` in cycle errors. Returns the merged config and
+/// warnings about merges that may not do what was meant.
+pub(crate) fn resolve(cfg_in: Value, label: &str, worktree: &Worktree) -> Result<(Value, Vec<String>)> {
+    if cfg_in.get("extends").is_none() {
+        return Ok((cfg_in, Vec::new()));
+    }
+    let sources = Sources::read(worktree)?;
+    let request = cfg_in.get("request").and_then(Value::as_str).unwrap_or("attach").to_string();
+    let mut warnings = Vec::new();
+    let mut chain = vec![label.to_string()];
+    let mut layers = vec![cfg_in];
+    loop {
+        let name = match layers.last_mut().and_then(|c| c.as_object_mut()).and_then(|c| c.remove("extends")) {
+            None | Some(Value::Null) => break,
+            Some(Value::String(name)) if !name.is_empty() => name,
+            Some(v) => {
+                let problem = format!("in `{}` is not a label", chain.last().unwrap());
+                return Err(ConfigError::new("extends", problem)
+                    .value(v)
+                    .hint("expected the label of another configuration")
+                    .into());
+            }
+        };
+        if chain.contains(&name) {
+            chain.push(name);
+            return Err(format!("`extends` cycle: {}", chain.join(" -> ")));
+        }
+        let mut base = sources.find(&name)?;
+        if let Some(inherited) = base.get("request").and_then(Value::as_str)
+            && inherited != request
+        {
+            warnings.push(format!(
+                "`{}` has \"request\": \"{}\", which isn't inherited: this session uses \"{}\"",
+                name, inherited, request
+            ));
+        }
+        if let Some(base) = base.as_object_mut() {
+            for key in NOT_INHERITED {
+                base.remove(key);
+            }
+        }
+        chain.push(name);
+        layers.push(base);
+    }
+
+    // Apply from the furthest base down to the session's own config
+    let mut merged = layers.pop().unwrap();
+    while let Some(layer) = layers.pop() {
+        let name = &chain[layers.len()];
+        let base = &chain[layers.len() + 1];
+        check_merge(&merged, &layer, "", name, base, &mut warnings);
+        merge_json(&mut merged, layer);
+    }
+    Ok((merged, warnings))
+}
+
+/// Warn where `layer` replaces an object from `base` with something else, or
+/// the other way round: only objects merge, everything else is overridden
+fn check_merge(base: &Value, layer: &Value, path: &str, name: &str, base_name: &str, warnings: &mut Vec<String>) {
+    let (Value::Object(base), Value::Object(layer)) = (base, layer) else {
+        return;
+    };
+    for (key, value) in layer {
+        let Some(inherited) = base.get(key) else {
+            continue;
+        };
+        let field = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match (inherited, value) {
+            (Value::Object(_), Value::Object(_)) => check_merge(inherited, value, &field, name, base_name, warnings),
+            (Value::Object(_), _) | (_, Value::Object(_)) => warnings.push(format!(
+                "`{}` in `{}` replaces the one from `{}` (only objects merge with objects)",
+                field, name, base_name
+            )),
+            _ => {}
+        }
+    }
+}
+
+/// The files `extends` names are looked up in
+struct Sources {
+    scenarios: Vec<Value>,
+    defaults: serde_json::Map<String, Value>,
+}
+
+impl Sources {
+    fn read(worktree: &Worktree) -> Result<Self> {
+        let parse = |path: &str| -> Result<Option<Value>> {
+            match worktree.read_text_file(path) {
+                Ok(text) => serde_json::from_str(&strip_jsonc(&text))
+                    .map(Some)
+                    .map_err(|e| format!("`extends`: invalid {}: {}", path, e)),
+                Err(_) => Ok(None),
+            }
+        };
+        let scenarios = match parse(DEBUG_JSON)? {
+            None => Vec::new(),
+            Some(Value::Array(scenarios)) => scenarios,
+            Some(_) => return Err(format!("`extends`: {} is not an array of configurations", DEBUG_JSON)),
+        };
+        let defaults = match parse(DEFAULTS_FILE)? {
+            None => serde_json::Map::new(),
+            Some(Value::Object(defaults)) => defaults,
+            Some(_) => {
+                return Err(format!(
+                    "`extends`: {} is not an object mapping names to configurations",
+                    DEFAULTS_FILE
+                ));
+            }
+        };
+        Ok(Self { scenarios, defaults })
+    }
+
+    /// The configuration labeled `name` in debug.json, else the one named
+    /// `name` in the defaults file
+    fn find(&self, name: &str) -> Result<Value> {
+        let scenario = self
+            .scenarios
+            .iter()
+            .find(|s| s.get("label").and_then(Value::as_str) == Some(name));
+        match scenario.or_else(|| self.defaults.get(name)) {
+            Some(base @ Value::Object(_)) => Ok(base.clone()),
+            Some(v) => Err(format!("`extends`: `{}` is {} (expected a configuration object)", name, v)),
+            None => Err(format!(
+                "`extends`: no configuration labeled `{}` in {} or {}",
+                name, DEBUG_JSON, DEFAULTS_FILE
+            )),
+        }
+    }
+}
+
+/// `text` with `//` and `/* */` comments and trailing commas blanked out, as
+/// Zed accepts them in its JSON files. Line numbers are kept for errors.
+fn strip_jsonc(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+                    prev = c;
+                }
+            }
+            ']' | '}' => {
+                let end = out.trim_end().len();
+                if out[..end].ends_with(',') {
+                    out.replace_range(end - 1..end, " ");
+                }
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,208 @@
+//! Host commands run around the session (`preConnectCommand`, `postSessionCommand`)
+
+use serde_json::Value;
+use zed_extension_api::{Result, process::Command};
+
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables, host, server::shell_quote};
+
+/// Seconds a hook may run when its `timeout` is not set
+const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 60;
+
+/// A shell command with its working directory, environment and time limit
+pub(crate) struct Hook {
+    key: &'static str,
+    command: String,
+    cwd: Option<String>,
+    env: Vec<(String, String)>,
+    timeout_secs: u64,
+}
+
+/// Parse a hook given as a shell command string or as a
+/// `{command, args, cwd, env, timeout}` object
+pub(crate) fn parse(cfg_in: &Value, key: &'static str, vars: &Variables) -> Result<Option<Hook>> {
+    let spec = match cfg_in.get(key) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(command)) if !command.is_empty() => {
+            return Ok(Some(Hook {
+                key,
+                command: expand_variables(command, vars),
+                cwd: None,
+                env: Vec::new(),
+                timeout_secs: DEFAULT_HOOK_TIMEOUT_SECS,
+            }));
+        }
+        Some(spec @ Value::Object(_)) => spec,
+        Some(v) => {
+            return Err(ConfigError::new(key, "not a hook")
+                .value(v)
+                .hint("expected a shell command or {\"command\": ..., \"args\": [...]}")
+                .into());
+        }
+    };
+
+    let string = |field: &str| match spec.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) if !s.is_empty() => Ok(Some(expand_variables(s, vars))),
+        Some(v) => Err(ConfigError::new(format!("{}.{}", key, field), "not a string").value(v)),
+    };
+    let program = string("command")?.ok_or_else(|| ConfigError::new(format!("{}.command", key), "is missing"))?;
+    // With `args` the command is a program, run without the shell's word splitting
+    let command = match spec.get("args") {
+        None | Some(Value::Null) => program,
+        Some(Value::Array(args)) => {
+            let mut words = vec![shell_quote(&program)];
+            for arg in args {
+                let arg = arg
+                    .as_str()
+                    .ok_or_else(|| ConfigError::new(format!("{}.args", key), "has an entry that is not a string")
+                        .value(arg))?;
+                words.push(shell_quote(&expand_variables(arg, vars)));
+            }
+            words.join(" ")
+        }
+        Some(v) => {
+            return Err(ConfigError::new(format!("{}.args", key), "not an array of strings")
+                .value(v)
+                .into());
+        }
+    };
+    let env = match spec.get("env") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Object(env)) => env
+            .iter()
+            .map(|(k, v)| match v {
+                _ if !is_env_name(k) => {
+                    Err(ConfigError::new(format!("{}.env", key), "has an invalid variable name")
+                        .value(format!("{:?}", k))
+                        .into())
+                }
+                Value::String(v) => Ok((k.clone(), expand_variables(v, vars))),
+                v => Err(ConfigError::new(format!("{}.env.{}", key, k), "not a string").value(v).into()),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(v) => return Err(ConfigError::new(format!("{}.env", key), "not an object").value(v).into()),
+    };
+    let timeout_secs = match spec.get("timeout") {
+        None | Some(Value::Null) => DEFAULT_HOOK_TIMEOUT_SECS,
+        Some(v) => v.as_u64().filter(|t| *t > 0).ok_or_else(|| {
+            ConfigError::new(format!("{}.timeout", key), "not a positive number of seconds").value(v)
+        })?,
+    };
+
+    Ok(Some(Hook {
+        key,
+        command,
+        cwd: string("cwd")?,
+        env,
+        timeout_secs,
+    }))
+}
+
+/// Whether `name` can be `export`ed by a POSIX shell
+fn is_env_name(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Hook {
+    /// Shell script that runs the command and kills it after the timeout, then
+    /// copies its output to stderr. The output goes through a temp file, so
+    /// neither the timer nor anything the command leaves running in the
+    /// background can hold the caller's pipes open.
+    fn script(&self) -> String {
+        let mut command = String::new();
+        if let Some(cwd) = &self.cwd {
+            command.push_str(&format!("cd {} && ", shell_quote(cwd)));
+        }
+        for (k, v) in &self.env {
+            command.push_str(&format!("export {}={} && ", k, shell_quote(v)));
+        }
+        command.push_str(&self.command);
+        format!(
+            r#"out=$(mktemp "${{TMPDIR:-/tmp}}/lldb-remote-hook.XXXXXX") || exit 1
+( {command}
+) >"$out" 2>&1 </dev/null &
+pid=$!
+( sleep {timeout}; kill "$pid" ) >/dev/null 2>&1 </dev/null &
+timer=$!
+wait "$pid"
+status=$?
+cat "$out" >&2
+rm -f "$out"
+if ! kill "$timer" 2>/dev/null; then
+  echo "lldb-remote: \`{key}\` timed out after {timeout}s" >&2
+  exit 124
+fi
+exit "$status""#,
+            command = command,
+            timeout = self.timeout_secs,
+            key = self.key,
+        )
+    }
+
+    /// Run the hook now and wait for it; a nonzero exit fails with its output
+    pub(crate) fn run(&self, env: Vec<(String, String)>) -> Result<()> {
+        host::run(
+            Command::new("sh").args(["-c", &self.script()]).envs(env),
+            &format!("`{}`", self.key),
+        )?;
+        Ok(())
+    }
+
+    /// Command and arguments that run `command` with `arguments` in place of the
+    /// shell, and the hook once that process is gone. A watchdog polls for the
+    /// exit, so the hook runs however the adapter ends, even if it is killed.
+    pub(crate) fn wrap(&self, command: String, arguments: Vec<String>) -> (String, Vec<String>) {
+        // Nobody is left to show the output to, so it goes to a log
+        let script = format!(
+            r#"wrapper=$$
+( while kill -0 "$wrapper" 2>/dev/null; do sleep 1; done
+{hook}
+) >"${{TMPDIR:-/tmp}}/lldb-remote-post-session.log" 2>&1 </dev/null &
+exec "$@""#,
+            hook = self.script(),
+        );
+        let mut wrapped = vec!["-c".to_string(), script, "lldb-remote".to_string(), command];
+        wrapped.extend(arguments);
+        ("sh".to_string(), wrapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(command: &str, timeout_secs: u64) -> Hook {
+        Hook {
+            key: "preConnectCommand",
+            command: command.to_string(),
+            cwd: None,
+            env: vec![("GREETING".to_string(), "hello world".to_string())],
+            timeout_secs,
+        }
+    }
+
+    fn run_script(hook: &Hook) -> (Option<i32>, String) {
+        let out = std::process::Command::new("sh").args(["-c", &hook.script()]).output().unwrap();
+        (out.status.code(), String::from_utf8_lossy(&out.stderr).into_owned())
+    }
+
+    #[test]
+    fn output_goes_to_stderr_with_the_exit_status() {
+        let (status, stderr) = run_script(&hook("echo \"$GREETING\"; echo oops >&2; exit 3", 5));
+        assert_eq!(status, Some(3));
+        assert_eq!(stderr, "hello world\noops\n");
+    }
+
+    #[test]
+    fn timeout_is_not_held_up_by_background_children() {
+        let start = std::time::Instant::now();
+        let (status, stderr) = run_script(&hook("echo started; sleep 8 & sleep 8", 1));
+        assert!(start.elapsed().as_secs() < 6, "took {:?}", start.elapsed());
+        assert_eq!(status, Some(124));
+        assert!(stderr.contains("started\n"), "{:?}", stderr);
+        assert!(stderr.contains("`preConnectCommand` timed out after 1s"), "{:?}", stderr);
+    }
+}
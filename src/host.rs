@@ -0,0 +1,62 @@
+//! Running helper commands on the host
+
+use zed_extension_api::{Result, process::Command, process::Output};
+
+/// Run `command` to completion, failing with its stderr if it exits nonzero.
+/// `description` names the command in error messages.
+pub(crate) fn run(mut command: Command, description: &str) -> Result<Output> {
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", description, e))?;
+    if output.status != Some(0) {
+        let code = output
+            .status
+            .map_or_else(|| "a signal".to_string(), |c| format!("status {}", c));
+        return Err(format!(
+            "{} exited with {}:\n{}",
+            description,
+            code,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(output)
+}
+
+/// Whether `path` is a directory on the host. Extensions can't stat host
+/// paths directly, so this asks `test -d`.
+pub(crate) fn is_dir(path: &str) -> Result<bool> {
+    test_path("-d", path)
+}
+
+/// Whether `path` is a regular file on the host, via `test -f`
+pub(crate) fn is_file(path: &str) -> Result<bool> {
+    test_path("-f", path)
+}
+
+/// Checks whether paths exist on the host
+pub(crate) trait FileCheck {
+    /// Whether `path` is a regular file
+    fn is_file(&self, path: &str) -> Result<bool>;
+}
+
+/// Checks with `test`, like the free functions here
+pub(crate) struct TestFileCheck;
+
+impl FileCheck for TestFileCheck {
+    fn is_file(&self, path: &str) -> Result<bool> {
+        is_file(path)
+    }
+}
+
+/// Whether anything exists at `path` on the host, sockets and pipes included, via `test -e`
+pub(crate) fn exists(path: &str) -> Result<bool> {
+    test_path("-e", path)
+}
+
+fn test_path(flag: &str, path: &str) -> Result<bool> {
+    let output = Command::new("test")
+        .args([flag, path])
+        .output()
+        .map_err(|e| format!("failed to run `test {} {}`: {}", flag, path, e))?;
+    Ok(output.status == Some(0))
+}
@@ -0,0 +1,190 @@
+//! Commands for debugging a Linux kernel through a gdb stub (e.g. qemu's `-s`)
+
+use serde_json::Value;
+use zed_extension_api::Result;
+
+use crate::command::CommandBuilder;
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables, parse_address, resolve_worktree_path};
+
+/// A parsed `kernel` block
+pub(crate) struct Kernel {
+    /// The kernel image, used as the session's program
+    pub(crate) vmlinux: String,
+    /// KASLR slide of the running kernel
+    slide: Option<u64>,
+    /// Loadable modules and the address of their `.text`
+    modules: Vec<(String, u64)>,
+    /// Python OS plugin presenting kernel tasks as threads
+    os_plugin: Option<String>,
+}
+
+/// Parse the `kernel` block, expanding variables in its paths
+pub(crate) fn parse(kernel: &Value, vars: &Variables, worktree_root: &str) -> Result<Kernel> {
+    let path = |value: Option<&Value>, key: &str| -> Result<Option<String>> {
+        match value {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(p)) if !p.is_empty() => Ok(Some(resolve_worktree_path(
+                &expand_variables(p, vars),
+                worktree_root,
+            ))),
+            Some(v) => Err(ConfigError::new(format!("kernel.{}", key), "not a path").value(v).into()),
+        }
+    };
+    let address = |value: &Value, key: &str| {
+        parse_address(value)
+            .ok_or_else(|| ConfigError::new(format!("kernel.{}", key), "not an address")
+                .value(value)
+                .hint("expected 0x hex or decimal"))
+    };
+
+    let vmlinux = path(kernel.get("vmlinux"), "vmlinux")?
+        .ok_or_else(|| ConfigError::new("kernel.vmlinux", "is missing")
+            .hint("expected the kernel image with debug info"))?;
+    let slide = match kernel.get("kaslrOffset") {
+        None | Some(Value::Null) => None,
+        Some(v) => Some(address(v, "kaslrOffset")?),
+    };
+    let modules = match kernel.get("extraModules") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(modules)) => modules
+            .iter()
+            .enumerate()
+            .map(|(i, module)| {
+                let key = format!("extraModules[{}]", i);
+                let module_path = path(module.get("path"), &format!("{}.path", key))?
+                    .ok_or_else(|| ConfigError::new(format!("kernel.{}.path", key), "is missing"))?;
+                let text = module
+                    .get("address")
+                    .ok_or_else(|| ConfigError::new(format!("kernel.{}.address", key), "is missing"))?;
+                Ok((module_path, address(text, &format!("{}.address", key))?))
+            })
+            .collect::<Result<_>>()?,
+        Some(v) => return Err(ConfigError::new("kernel.extraModules", "not an array").value(v).into()),
+    };
+    let os_plugin = path(kernel.get("osPlugin"), "osPlugin")?;
+
+    Ok(Kernel {
+        vmlinux,
+        slide,
+        modules,
+        os_plugin,
+    })
+}
+
+impl Kernel {
+    /// Settings needed before the target is created
+    pub(crate) fn init_commands(&self) -> Vec<String> {
+        self.os_plugin
+            .iter()
+            .map(|plugin| {
+                CommandBuilder::new("settings set target.process.python-os-plugin-path")
+                    .arg(plugin)
+                    .build()
+            })
+            .collect()
+    }
+
+    /// Commands between `target create` and connecting: the KASLR slide
+    pub(crate) fn pre_connect_commands(&self) -> Vec<String> {
+        self.slide
+            .iter()
+            .map(|slide| {
+                CommandBuilder::new("target modules load")
+                    .word("--file")
+                    .arg(&self.vmlinux)
+                    .word("--slide")
+                    .word(format!("{:#x}", slide))
+                    .build()
+            })
+            .collect()
+    }
+
+    /// Commands after connecting: modules were loaded by the running kernel
+    pub(crate) fn post_connect_commands(&self) -> Vec<String> {
+        self.modules
+            .iter()
+            .flat_map(|(path, text)| {
+                [
+                    CommandBuilder::new("target modules add").arg(path).build(),
+                    CommandBuilder::new("target modules load")
+                        .word("--file")
+                        .arg(path)
+                        .word(".text")
+                        .word(format!("{:#x}", text))
+                        .build(),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn vars() -> Variables {
+        Variables {
+            home: "/home/me".to_string(),
+            target_triple: None,
+            allow_shell_commands: false,
+            shell_env: Vec::new(),
+            shell_timeout_secs: 5,
+            shell_warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn commands_go_around_the_connect_in_order() {
+        let kernel = parse(
+            &json!({
+                "vmlinux": "build/vmlinux",
+                "kaslrOffset": "0x1e000000",
+                "extraModules": [{"path": "${HOME}/mods/e1000.ko", "address": "0xffffffffc0200000"}],
+                "osPlugin": "scripts/tasks.py",
+            }),
+            &vars(),
+            "/linux",
+        )
+        .unwrap();
+        assert_eq!(kernel.vmlinux, "/linux/build/vmlinux");
+        assert_eq!(
+            kernel.init_commands(),
+            [r#"settings set target.process.python-os-plugin-path "/linux/scripts/tasks.py""#]
+        );
+        assert_eq!(
+            kernel.pre_connect_commands(),
+            [r#"target modules load --file "/linux/build/vmlinux" --slide 0x1e000000"#]
+        );
+        assert_eq!(
+            kernel.post_connect_commands(),
+            [
+                r#"target modules add "/home/me/mods/e1000.ko""#,
+                r#"target modules load --file "/home/me/mods/e1000.ko" .text 0xffffffffc0200000"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn addresses_are_hex_or_decimal() {
+        let kernel = parse(&json!({"vmlinux": "/vmlinux", "kaslrOffset": "4096"}), &vars(), "").unwrap();
+        assert_eq!(kernel.pre_connect_commands(), [r#"target modules load --file "/vmlinux" --slide 0x1000"#]);
+        let kernel = parse(&json!({"vmlinux": "/vmlinux"}), &vars(), "").unwrap();
+        assert!(kernel.pre_connect_commands().is_empty());
+
+        let error = |kernel: Value| parse(&kernel, &vars(), "").err().unwrap();
+        assert_eq!(
+            error(json!({"vmlinux": "/vmlinux", "kaslrOffset": "0xgg"})),
+            "`kernel.kaslrOffset`: not an address\n  value: \"0xgg\"\n  hint: expected 0x hex or decimal"
+        );
+        assert_eq!(
+            error(json!({"vmlinux": "/vmlinux", "extraModules": [{"path": "/m.ko"}]})),
+            "`kernel.extraModules[0].address`: is missing"
+        );
+        assert!(error(json!({})).starts_with("`kernel.vmlinux`: is missing"));
+    }
+}
@@ -1,28 +1,236 @@
+mod cargo;
+mod command;
+mod config;
+mod config_hash;
+mod deploy;
+mod elevate;
+mod error;
+mod extends;
+mod hooks;
+mod host;
+mod kernel;
+mod pattern;
+mod preflight;
+mod process_list;
+mod scenario;
+mod server;
+mod svd;
+mod wsl;
+
+use command::CommandBuilder;
+use config::{Connection, OneOrMany, PathMappingEntry, RemoteDebugConfig, USER_COMMAND_KEYS};
+use config_hash::Fingerprint;
+use error::ConfigError;
+
 use serde_json::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use zed::{
-    DebugAdapterBinary, Extension, Result, StartDebuggingRequestArguments,
-    StartDebuggingRequestArgumentsRequest, Worktree,
+    DebugAdapterBinary, DebugConfig, DebugRequest, DebugScenario, Extension, Result,
+    StartDebuggingRequestArguments, StartDebuggingRequestArgumentsRequest, TaskTemplate, Worktree,
 };
 use zed_extension_api as zed;
 
+/// Name of this extension's debug adapter, as registered in extension.toml
+const ADAPTER_NAME: &str = "lldb-remote";
+
+/// `maxRestarts` values above this are accepted but flagged as suspicious
+const MAX_RESTARTS_WARN_THRESHOLD: u32 = 5;
+
+/// `sourceLanguage` values LLDB is known to accept; others get a warning
+const KNOWN_SOURCE_LANGUAGES: &[&str] = &[
+    "c",
+    "c++",
+    "objective-c",
+    "objective-c++",
+    "rust",
+    "swift",
+];
+
+/// The schema Zed validates configs with; its properties are the fields the extension reads
+const CONFIG_SCHEMA: &str = include_str!("../debug_adapter_schemas/lldb-remote.json");
+
+/// Fields of a debug.json entry that Zed itself reads
+const SCENARIO_FIELDS: &[&str] = &["label", "adapter", "build", "tcp_connection"];
+
+/// Seconds a `$(command)` may run when `shellExpansionTimeout` is not set
+const DEFAULT_SHELL_EXPANSION_TIMEOUT_SECS: u64 = 5;
+
+/// Configurations kept per adapter for sessions started in an interleaved order
+const MAX_CAPTURED_CONFIGS: usize = 16;
+
+/// A configuration seen by `dap_request_kind`, for the `get_dap_binary` of the same session
+#[derive(Clone)]
+struct CapturedConfig {
+    json: String,
+    /// Fingerprint of `json`, matched against the config `get_dap_binary` gets
+    fingerprint: Fingerprint,
+    request_kind: StartDebuggingRequestArgumentsRequest,
+}
+
 struct Ext {
-    last_config_json: Option<String>,
-    last_request_kind: Option<StartDebuggingRequestArgumentsRequest>,
+    /// Captured configurations keyed by adapter name, most recent last
+    captured_configs: HashMap<String, Vec<CapturedConfig>>,
+    /// `cargo metadata` results keyed by worktree id
+    cargo_metadata: HashMap<u64, cargo::Metadata>,
+    /// Checksums of binaries already deployed, keyed by (program, copyTo)
+    deployed: HashMap<(String, String), String>,
+    /// Detected lldb-dap major versions keyed by adapter command
+    lldb_dap_versions: HashMap<String, Option<u32>>,
+    /// Last binary built from each config with `cacheConfig`, keyed by label,
+    /// reused while the config is broken. Lives only as long as the loaded extension.
+    last_good_binaries: HashMap<String, DebugAdapterBinary>,
+    /// Addresses of lldb-dap servers started for a `sessionId`, which
+    /// `reAttach` connects to instead of starting another adapter
+    dap_sessions: HashMap<String, zed::TcpArguments>,
+}
+
+impl Ext {
+    /// Take the captured configuration for a session of `adapter_name` whose
+    /// config has the fingerprint `actual`, or the latest one when Zed passes no
+    /// config. Two sessions whose calls interleave each get their own. Taking it
+    /// leaves nothing stale behind for a later `get_dap_binary` to pick up.
+    fn clear_pending_session(
+        &mut self,
+        adapter_name: &str,
+        actual: Option<Fingerprint>,
+    ) -> Result<CapturedConfig> {
+        let captured = self.captured_configs.get_mut(adapter_name);
+        let Some(captured) = captured.filter(|c| !c.is_empty()) else {
+            return Err(ConfigError::new(
+                "request",
+                format!("was never determined for this `{}` session (dap_request_kind was not called)", adapter_name),
+            )
+            .hint("start the session again")
+            .into());
+        };
+        let index = match actual {
+            None => captured.len() - 1,
+            Some(actual) => match captured.iter().rposition(|c| c.fingerprint == actual) {
+                Some(index) => index,
+                // dap_request_kind ran for a different (or newer) configuration
+                None => {
+                    return Err(ConfigError::new(
+                        "request",
+                        "was determined for a different configuration; this one changed since",
+                    )
+                    .value(format!("fingerprint {} vs {}", captured[captured.len() - 1].fingerprint, actual))
+                    .hint("start the session again")
+                    .into());
+                }
+            },
+        };
+        Ok(captured.remove(index))
+    }
+
+    /// Fall back to the last known-good binary of the config labeled `label`
+    /// when it is broken, telling the user so in the debug console; without
+    /// one, fail with `error`
+    fn cached_binary(&self, label: &str, error: String) -> Result<DebugAdapterBinary> {
+        let Some(mut binary) = self.last_good_binaries.get(label).cloned() else {
+            return Err(error);
+        };
+        let mut cfg: Value = serde_json::from_str(&binary.request_args.configuration)
+            .map_err(|e| format!("invalid cached lldb-dap configuration: {}", e))?;
+        let mut init_cmds = vec![serde_json::json!(warning_command(&format!(
+            "{}; reusing the last configuration that worked",
+            error
+        )))];
+        if let Some(cmds) = cfg.get("initCommands").and_then(|v| v.as_array()) {
+            init_cmds.extend(cmds.iter().cloned());
+        }
+        cfg["initCommands"] = Value::Array(init_cmds);
+        binary.request_args.configuration = cfg.to_string();
+        Ok(binary)
+    }
+
+    /// Fall back to the workspace's only binary when `program` is omitted
+    fn cargo_default_program(
+        &mut self,
+        cfg_in: &Value,
+        worktree: &Worktree,
+        vars: &Variables,
+    ) -> Result<Option<String>> {
+        if worktree.read_text_file("Cargo.toml").is_err() {
+            return Ok(None);
+        }
+        let metadata = match self.cargo_metadata.get(&worktree.id()) {
+            Some(metadata) => metadata,
+            None => {
+                let metadata = cargo::metadata(worktree)?;
+                self.cargo_metadata.entry(worktree.id()).or_insert(metadata)
+            }
+        };
+        let profile = cfg_in
+            .get("profile")
+            .and_then(|v| v.as_str())
+            .unwrap_or("debug");
+        let triple = vars.target_triple.as_deref();
+        let program = metadata.default_program(profile, triple)?;
+
+        // A missing triple directory means the host binary would be picked up by mistake
+        if program.is_some()
+            && let (Some(triple), Some(profile_dir)) = (triple, metadata.profile_dir(profile, triple))
+            && !worktree_file_exists(
+                worktree,
+                &worktree.root_path(),
+                &format!("{}/.cargo-lock", profile_dir),
+            )
+        {
+            return Err(format!(
+                "{} does not exist; run the cross build first (cargo build --target {})",
+                profile_dir, triple
+            ));
+        }
+        Ok(program)
+    }
+
+    /// Run `<command> --version` once per command and remember the major version
+    fn lldb_dap_major_version(&mut self, command: &str, worktree: &Worktree) -> Option<u32> {
+        *self
+            .lldb_dap_versions
+            .entry(command.to_string())
+            .or_insert_with(|| {
+                zed::process::Command::new(command)
+                    .arg("--version")
+                    .envs(worktree.shell_env())
+                    .output()
+                    .ok()
+                    .and_then(|out| parse_lldb_version(&String::from_utf8_lossy(&out.stdout)))
+            })
+    }
 }
 
 /// Infer home directory from a path like /home/john/...
 fn infer_home_from_path(path: &str) -> String {
-    if let Some(start) = path.find("/home/") {
-        if let Some(end) = path[start + 6..].find('/') {
-            return format!("/home/{}", &path[start + 6..start + 6 + end]);
-        }
+    if let Some(start) = path.find("/home/")
+        && let Some(end) = path[start + 6..].find('/')
+    {
+        return format!("/home/{}", &path[start + 6..start + 6 + end]);
     }
     std::env::var("HOME").unwrap_or_default()
 }
 
-/// Expand common variables in paths: ${HOME}, ${USER}
-fn expand_variables(path: &str, home: &str) -> String {
+/// Values available to `expand_variables`
+struct Variables {
+    home: String,
+    target_triple: Option<String>,
+    /// Whether `$(command)` is run through the host shell (`allowShellExpansion`)
+    allow_shell_commands: bool,
+    /// The worktree's shell environment, for `${env:NAME}` and `$(command)`
+    shell_env: Vec<(String, String)>,
+    /// Seconds a `$(command)` may run before it is left unexpanded (`shellExpansionTimeout`)
+    shell_timeout_secs: u64,
+    /// `$(command)`s that timed out, for the debug console
+    shell_warnings: RefCell<Vec<String>>,
+}
+
+/// Expand common variables in paths: ${HOME}, ${USER}, ${targetTriple},
+/// ${env:NAME}, and `$(command)` when shell expansion is allowed
+fn expand_variables(path: &str, vars: &Variables) -> String {
     let mut result = path.to_string();
+    let home = vars.home.as_str();
 
     if !home.is_empty() {
         result = result.replace("${HOME}", home);
@@ -35,198 +243,3697 @@ fn expand_variables(path: &str, home: &str) -> String {
         result = result.replace("$USER", user);
     }
 
+    if let Some(triple) = &vars.target_triple {
+        result = result.replace("${targetTriple}", triple);
+    }
+
+    result = expand_env_refs(&result, &vars.shell_env);
+
+    if vars.allow_shell_commands {
+        result = expand_shell_commands(&result, vars);
+    }
+
     result
 }
 
-impl Extension for Ext {
-    fn new() -> Self {
-        Self {
-            last_config_json: None,
-            last_request_kind: None,
+/// Replace each `${env:NAME}` with the variable's value. Unset variables are
+/// left as written, so they show up in whatever error they cause.
+fn expand_env_refs(input: &str, env: &[(String, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("${env:") {
+        result.push_str(&rest[..start]);
+        let inner = &rest[start + "${env:".len()..];
+        let Some(end) = inner.find('}') else {
+            break;
+        };
+        let name = &inner[..end];
+        match env.iter().find(|(k, _)| k == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..start + "${env:".len() + end + 1]),
         }
+        rest = &inner[end + 1..];
     }
+    result.push_str(rest);
+    result
+}
 
-    // Capture the user's .zed/debug.json entry and decide attach/launch.
-    fn dap_request_kind(
-        &mut self,
-        _adapter_name: String,
-        config: Value,
-    ) -> Result<StartDebuggingRequestArgumentsRequest> {
-        // Save exact JSON to reuse later
-        self.last_config_json = Some(config.to_string());
+/// Runs `$1` with a limit of `$2` seconds, exiting 124 when it is killed. The
+/// output goes through a file, so a process the command left behind can't keep
+/// the pipe, and the caller, waiting.
+const SHELL_EXPANSION_SCRIPT: &str = r#"out=$(mktemp) || exit 1
+( eval "$1" ) >"$out" 2>/dev/null </dev/null &
+pid=$!
+( sleep "$2"; kill "$pid" ) >/dev/null 2>&1 </dev/null &
+timer=$!
+wait "$pid"
+status=$?
+if ! kill "$timer" 2>/dev/null; then
+  rm -f "$out"
+  exit 124
+fi
+cat "$out"
+rm -f "$out"
+exit "$status""#;
 
-        // Decide request kind
-        let req = match config
-            .get("request")
-            .and_then(|v| v.as_str())
-            .unwrap_or("attach")
-        {
-            "launch" => StartDebuggingRequestArgumentsRequest::Launch,
-            _ => StartDebuggingRequestArgumentsRequest::Attach,
-        };
-        self.last_request_kind = Some(req.clone());
-        Ok(req)
+/// Parse the captured JSON. Only an empty configuration means "no options";
+/// a malformed one would otherwise surface as a confusing missing-field error.
+fn parse_captured_config(json: &str) -> Result<Value> {
+    match json.trim() {
+        "" => Ok(serde_json::json!({})),
+        // serde_json's message includes the line and column
+        json => serde_json::from_str(json).map_err(|e| format!("invalid debug configuration JSON: {}", e)),
     }
+}
 
-    // Spawn lldb-dap and pass only what it needs.
-    fn get_dap_binary(
-        &mut self,
-        _adapter_name: String,
-        _config: zed::DebugTaskDefinition,
-        _user_provided_debug_adapter_path: Option<String>,
-        worktree: &Worktree,
-    ) -> Result<DebugAdapterBinary> {
-        // Parse the captured JSON
-        let cfg_in: serde_json::Value = self
-            .last_config_json
-            .as_deref()
-            .and_then(|s| serde_json::from_str(s).ok())
-            .unwrap_or(serde_json::json!({}));
-
-        // Get home directory from worktree path
-        let worktree_root = worktree.root_path();
-        let home = infer_home_from_path(&worktree_root);
-
-        // Always attach (that’s our scenario); compute the request enum
-        let request = self
-            .last_request_kind
-            .clone()
-            .unwrap_or(StartDebuggingRequestArgumentsRequest::Attach);
-
-        // Extract tcp://HOST:PORT
-        let tcp_addr = cfg_in
-            .get("target")
-            .and_then(|v| v.as_str())
-            .and_then(|s| s.strip_prefix("tcp://"))
-            .ok_or_else(|| "missing or invalid `target` (expected tcp://HOST:PORT)".to_string())?
-            .to_string();
-
-        // // Build the minimal lldb-dap configuration
-        // // NOTE: we intentionally do NOT include program/pid/pathMappings here
-        // let mut cfg_out = serde_json::json!({
-        //     "request": "attach",
-        //     "attachCommands": [ format!("gdb-remote {}", tcp_addr) ]
-        // });
-        // Build attach commands
-        let mut attach_cmds = Vec::new();
+/// The `sh` argv for one `$(command)`; extension.toml declares a capability
+/// of exactly this shape.
+fn shell_expansion_args<'a>(command: &'a str, timeout: &'a str) -> [&'a str; 5] {
+    ["-c", SHELL_EXPANSION_SCRIPT, "lldb-remote", command, timeout]
+}
 
-        // If program is provided, create target BEFORE gdb-remote
-        if let Some(program) = cfg_in.get("program").and_then(|v| v.as_str()) {
-            let program = expand_variables(program, &home);
-            attach_cmds.push(format!("target create {}", program));
-        }
+/// Replace each `$(command)` with the command's trimmed stdout. Commands that
+/// fail or time out, and unbalanced `$(`, are left as written.
+fn expand_shell_commands(input: &str, vars: &Variables) -> String {
+    let mut result = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("$(") {
+        result.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
 
-        // Then connect via gdb-remote
-        attach_cmds.push(format!("gdb-remote {}", tcp_addr));
+        // Find the matching `)` so nested `$(...)` stay inside the command
+        let mut depth = 1;
+        let end = inner.char_indices().find_map(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i)
+        });
+        let Some(end) = end else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
 
-        // Then append user's attachCommands
-        if let Some(post) = cfg_in.get("attachCommands").and_then(|v| v.as_array()) {
-            for c in post {
-                if let Some(s) = c.as_str() {
-                    attach_cmds.push(s.to_string());
-                }
+        let token = &rest[start..start + 2 + end + 1];
+        let timeout = vars.shell_timeout_secs.to_string();
+        let output = zed::process::Command::new("sh")
+            .args(shell_expansion_args(&inner[..end], &timeout))
+            .envs(vars.shell_env.clone())
+            .output();
+        match output {
+            Ok(out) if out.status == Some(0) => {
+                result.push_str(String::from_utf8_lossy(&out.stdout).trim())
             }
+            Ok(out) if out.status == Some(124) => {
+                vars.shell_warnings.borrow_mut().push(format!(
+                    "{} timed out after {}s and was left unexpanded (see `shellExpansionTimeout`)",
+                    token, vars.shell_timeout_secs
+                ));
+                result.push_str(token)
+            }
+            _ => result.push_str(token),
         }
+        rest = &inner[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
 
-        // Build outgoing configuration
-        let mut cfg_out = serde_json::json!({
-            "request": "attach",
-            "attachCommands": attach_cmds
-        });
+/// Read the cross-compilation triple from `cargoTarget` or its alias `targetTriple`
+fn target_triple(cfg_in: &Value) -> Result<Option<String>> {
+    let get = |key: &str| -> Result<Option<String>> {
+        match cfg_in.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) if !s.is_empty() => Ok(Some(s.clone())),
+            Some(v) => {
+                Err(ConfigError::new(key, "not a target triple")
+                    .value(v)
+                    .hint("expected e.g. \"aarch64-unknown-linux-gnu\"")
+                    .into())
+            }
+        }
+    };
+    match (get("cargoTarget")?, get("targetTriple")?) {
+        (Some(a), Some(b)) if a != b => Err(format!(
+            "`cargoTarget` ({}) and `targetTriple` ({}) disagree",
+            a, b
+        )),
+        (a, b) => Ok(a.or(b)),
+    }
+}
 
-        // Preserve stopOnEntry if present
-        if let Some(soe) = cfg_in.get("stopOnEntry") {
-            cfg_out
-                .as_object_mut()
-                .unwrap()
-                .insert("stopOnEntry".into(), soe.clone());
+/// Platform overlays, by the key a config uses for each host OS
+const PLATFORM_OVERLAYS: [(&str, zed::Os); 3] = [
+    ("linux", zed::Os::Linux),
+    ("macos", zed::Os::Mac),
+    ("windows", zed::Os::Windows),
+];
+
+/// Merge the overlay for the host OS (`"linux"`, `"macos"` or `"windows"`)
+/// over the rest of the config, and drop the overlays. Objects merge key by
+/// key; anything else, arrays included, replaces what the base config had.
+fn apply_platform_overlay(mut cfg_in: Value) -> Result<Value> {
+    let Some(obj) = cfg_in.as_object_mut() else {
+        return Ok(cfg_in);
+    };
+    let host = zed::current_platform().0;
+    let mut overlay = None;
+    for (key, os) in PLATFORM_OVERLAYS {
+        match obj.remove(key) {
+            Some(v @ Value::Object(_)) if os == host => overlay = Some(v),
+            Some(Value::Null) | None => {}
+            Some(v) if os == host => {
+                return Err(ConfigError::new(key, "not an object of config fields").value(v).into());
+            }
+            // Another platform's overlay
+            Some(_) => {}
         }
+    }
+    if let Some(overlay) = overlay {
+        merge_json(&mut cfg_in, overlay);
+    }
+    Ok(cfg_in)
+}
 
-        // DO NOT forward program - we handle it in attachCommands instead
-        // This prevents lldb-dap from loading symbols before gdb-remote connects
+/// A warning naming the top-level fields nothing reads, which are usually
+/// typos like `pathMapping` that would otherwise silently do nothing
+fn unknown_fields_warning(cfg_in: &Value) -> Option<String> {
+    let schema: Value = serde_json::from_str(CONFIG_SCHEMA).ok()?;
+    let known = schema.get("properties")?.as_object()?;
+    let unknown: Vec<String> = cfg_in
+        .as_object()?
+        .keys()
+        .filter(|key| !known.contains_key(*key) && !SCENARIO_FIELDS.contains(&key.as_str()))
+        .map(|key| match closest_field(key, known.keys()) {
+            Some(similar) => format!("`{}` (did you mean `{}`?)", key, similar),
+            None => format!("`{}`", key),
+        })
+        .collect();
+    match unknown.len() {
+        0 => None,
+        1 => Some(format!("unknown field {} is not used", unknown[0])),
+        _ => Some(format!("unknown fields {} are not used", unknown.join(", "))),
+    }
+}
 
-        // Forward pathMappings if present, with variable expansion
-        if let Some(mappings) = cfg_in.get("pathMappings").and_then(|v| v.as_array()) {
-            let expanded_mappings: Vec<serde_json::Value> = mappings
-                .iter()
-                .map(|mapping| {
-                    let mut new_mapping = mapping.clone();
-                    if let Some(obj) = new_mapping.as_object_mut() {
-                        if let Some(local) = obj.get("localRoot").and_then(|v| v.as_str()) {
-                            obj.insert("localRoot".into(), serde_json::json!(expand_variables(local, &home)));
-                        }
-                        if let Some(remote) = obj.get("remoteRoot").and_then(|v| v.as_str()) {
-                            obj.insert("remoteRoot".into(), serde_json::json!(expand_variables(remote, &home)));
-                        }
-                    }
-                    new_mapping
-                })
-                .collect();
+/// The known field a couple of edits away from `key`, if there is one
+fn closest_field<'a>(key: &str, known: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let key = key.to_lowercase();
+    known
+        .map(|field| (edit_distance(&key, &field.to_lowercase()), field))
+        .filter(|(distance, _)| *distance <= 2 && *distance < key.len() / 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
 
-            cfg_out
-                .as_object_mut()
-                .unwrap()
-                .insert("pathMappings".into(), serde_json::json!(expanded_mappings));
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
         }
+    }
+    row[b.len()]
+}
 
-        // Forward env from debug.json (e.g., DEBUGINFOD_URLS) to the adapter process
-        let mut envs: Vec<(String, String)> = Vec::new();
-        if let Some(obj) = cfg_in.get("env").and_then(|v| v.as_object()) {
-            for (k, v) in obj {
-                if let Some(s) = v.as_str() {
-                    envs.push((k.clone(), s.to_string()));
-                } else {
-                    envs.push((k.clone(), v.to_string()));
+fn merge_json(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
                 }
             }
         }
+        (base, overlay) => *base = overlay,
+    }
+}
 
-        // Build initCommands: start with user's, then add auto-generated source-map from pathMappings
-        let mut init_cmds = Vec::new();
+/// Fix a Unix remote path written with backslashes on Windows, like
+/// `/build\myproject`. Only paths that also contain a forward slash are
+/// touched, so `C:\build` and UNC paths like `\\share\proj` are left alone.
+fn normalize_remote_path(s: &str) -> String {
+    if s.contains('/') {
+        s.replace('\\', "/")
+    } else {
+        s.to_string()
+    }
+}
 
-        // First, add user's initCommands if provided
-        if let Some(inits) = cfg_in.get("initCommands").and_then(|v| v.as_array()) {
-            for c in inits {
-                if let Some(s) = c.as_str() {
-                    init_cmds.push(s.to_string());
-                }
+/// Strip trailing slashes, keeping `/` and drive roots like `C:/` intact
+fn normalize_path_trailing_slash(s: &str) -> String {
+    let trimmed = s.trim_end_matches(['/', '\\']);
+    if trimmed.len() == s.len() {
+        return s.to_string();
+    }
+    let is_drive = trimmed.len() == 2
+        && trimmed.ends_with(':')
+        && trimmed.as_bytes()[0].is_ascii_alphabetic();
+    if trimmed.is_empty() || is_drive {
+        // Keep exactly one separator for a root
+        s[..trimmed.len() + 1].to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// A parsed `target` address
+struct Target {
+    /// `HOST:PORT` as passed to `gdb-remote`
+    addr: String,
+    /// Whether the connection is TLS-wrapped (`tcps://`)
+    tls: bool,
+}
+
+/// One `target.source-map` entry: paths starting with `remote` are looked up under `local`
+#[derive(Debug, PartialEq, Eq)]
+struct PathMapping {
+    remote: String,
+    local: String,
+}
+
+impl Ord for PathMapping {
+    /// Longest `remote` first, so `/build/external` is matched before `/build`
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .remote
+            .len()
+            .cmp(&self.remote.len())
+            .then_with(|| self.remote.cmp(&other.remote))
+            .then_with(|| self.local.cmp(&other.local))
+    }
+}
+
+impl PartialOrd for PathMapping {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Lowercase the host part of `HOST:PORT` so the same server always yields the
+/// same address string. The port and bracketed IPv6 hosts are left untouched.
+fn normalize_tcp_addr(s: &str) -> String {
+    match s.rsplit_once(':') {
+        Some((host, port)) if !host.starts_with('[') => {
+            format!("{}:{}", host.to_lowercase(), port)
+        }
+        _ => s.to_string(),
+    }
+}
+
+/// Decode `%XX` escapes in the host of a `tcp://` URL. A `%` not followed by
+/// two hex digits is kept as it is.
+fn url_decode_host(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .filter(|h| h.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
             }
         }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
 
-        // Then auto-generate source-map settings from pathMappings
-        if let Some(mappings) = cfg_in.get("pathMappings").and_then(|v| v.as_array()) {
-            for mapping in mappings {
-                if let (Some(remote), Some(local)) = (
-                    mapping.get("remoteRoot").and_then(|v| v.as_str()),
-                    mapping.get("localRoot").and_then(|v| v.as_str()),
-                ) {
-                    // Expand common variables in paths
-                    let remote = expand_variables(remote, &home);
-                    let local = expand_variables(local, &home);
-                    init_cmds.push(format!("settings set target.source-map {} {}", remote, local));
+/// Extract the `HOST:PORT` that `gdb-remote` expects from a `target` value.
+/// A scheme-less `HOST:PORT` is treated as TCP. Errors are reported for
+/// `target`; callers parsing another field's address re-key them.
+fn parse_target(target: &str) -> std::result::Result<Target, ConfigError> {
+    let (addr, tls) = if let Some(addr) = target.strip_prefix("tcp://") {
+        (addr, false)
+    } else if let Some(addr) = target.strip_prefix("tcps://") {
+        (addr, true)
+    } else if target.contains("://") {
+        return Err(ConfigError::new("target", "unsupported scheme")
+            .value(format!("{:?}", target))
+            .hint("expected tcp://HOST:PORT or tcps://HOST:PORT"));
+    } else {
+        (target, false)
+    };
+
+    // A slash pasted from a URL is harmless, anything after it isn't
+    let addr = addr.trim_end_matches('/');
+    if let Some(i) = addr.find(['/', '?', '#']) {
+        return Err(ConfigError::new("target", format!("unexpected {:?} after the port", &addr[i..]))
+            .value(format!("{:?}", target))
+            .hint("expected HOST:PORT only"));
+    }
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            // URL-generating tools percent-encode the host, e.g. tcp://my%20host:1234
+            let addr = if target.contains("://") {
+                let host = url_decode_host(host);
+                if host.contains(':') && !host.starts_with('[') {
+                    format!("[{}]:{}", host, port)
+                } else {
+                    format!("{}:{}", host, port)
                 }
+            } else {
+                addr.to_string()
+            };
+            Ok(Target {
+                addr: normalize_tcp_addr(&addr),
+                tls,
+            })
+        }
+        _ => Err(ConfigError::new("target", "not a HOST:PORT address")
+            .value(format!("{:?}", target))
+            .hint("expected tcp://HOST:PORT or HOST:PORT with a numeric port")),
+    }
+}
+
+/// Build the equivalent `target` string from a `connection` object
+fn target_from_connection(connection: &Connection) -> String {
+    // IPv6 literals need brackets to keep the port separable
+    let host = if connection.host.contains(':') && !connection.host.starts_with('[') {
+        format!("[{}]", connection.host)
+    } else {
+        connection.host.clone()
+    };
+    format!("{}://{}:{}", connection.protocol.as_deref().unwrap_or("tcp"), host, connection.port)
+}
+
+/// Build the `target` string from a `portFromFile` block: the port is the first
+/// capture group (or the whole match) of `pattern` on the file's last matching line
+fn target_from_port_file(
+    spec: &Value,
+    worktree: &Worktree,
+    worktree_root: &str,
+    vars: &Variables,
+) -> Result<String> {
+    let field = |key: &str| match spec.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) if !s.is_empty() => Ok(Some(s.as_str())),
+        Some(v) => Err(ConfigError::new(format!("portFromFile.{}", key), "not a string").value(v)),
+    };
+    let path = field("path")?.ok_or_else(|| ConfigError::new("portFromFile.path", "is missing"))?;
+    let pattern = field("pattern")?.ok_or_else(|| ConfigError::new("portFromFile.pattern", "is missing"))?;
+    let host = field("host")?.map_or_else(|| "127.0.0.1".to_string(), |h| expand_variables(h, vars));
+
+    let regex = pattern::Pattern::new(pattern)
+        .map_err(|e| ConfigError::new("portFromFile.pattern", e).value(format!("{:?}", pattern)))?;
+    let path = resolve_worktree_path(&expand_variables(path, vars), worktree_root);
+    let text = worktree
+        .read_text_file(worktree_relative(&path, worktree_root))
+        .map_err(|e| format!("cannot read `portFromFile.path` {}: {}", path, e))?;
+
+    // Logs appended to across runs end with the current one
+    let captures = text.lines().rev().find_map(|line| regex.captures(line)).ok_or_else(|| {
+        format!("`portFromFile.pattern` {:?} does not match anything in {}", pattern, path)
+    })?;
+    // The first group that took part, else the whole match
+    let port = captures[1..]
+        .iter()
+        .flatten()
+        .next()
+        .or(captures[0].as_ref())
+        .unwrap();
+    let port = port.trim().parse::<u16>().ok().filter(|p| *p > 0).ok_or_else(|| {
+        format!(
+            "`portFromFile.pattern` matched {:?} in {}, which is not a port (capture the number with a group)",
+            port, path
+        )
+    })?;
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host
+    };
+    Ok(format!("{}:{}", host, port))
+}
+
+impl Target {
+    /// Host part of the address, without IPv6 brackets
+    fn host(&self) -> &str {
+        let host = self.addr.rsplit_once(':').map_or(self.addr.as_str(), |(h, _)| h);
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+
+    /// Port part of the address
+    fn port(&self) -> &str {
+        self.addr.rsplit_once(':').map_or("", |(_, p)| p)
+    }
+}
+
+/// What the placeholders in user commands stand for
+struct Placeholders<'a> {
+    target: Option<&'a Target>,
+    program: Option<&'a str>,
+}
+
+/// Substitute `{target}`, `{host}`, `{port}` and `{program}` in a user command.
+/// Any other braces are left as written, as are these when there is no target
+/// or program.
+fn substitute_placeholders(cmd: &str, placeholders: &Placeholders) -> String {
+    let mut cmd = match placeholders.target {
+        Some(target) => cmd
+            .replace("{target}", &target.addr)
+            .replace("{host}", target.host())
+            .replace("{port}", target.port()),
+        None => cmd.to_string(),
+    };
+    if let Some(program) = placeholders.program {
+        cmd = cmd.replace("{program}", program);
+    }
+    cmd
+}
+
+/// The warning for user commands that use `{program}` in a session without one
+fn unfilled_program_warning(flags: &RemoteDebugConfig) -> Option<String> {
+    USER_COMMAND_KEYS
+        .iter()
+        .any(|key| flags.commands(key).iter().any(|c| c.contains("{program}")))
+        .then(|| "commands use `{program}` but there is no `program`, so it is left as written".to_string())
+}
+
+/// Split a command file into commands, tolerating files written on Windows:
+/// a leading BOM is dropped, CRLF is treated as LF, trailing whitespace is
+/// trimmed and blank lines are skipped
+fn parse_command_file(text: &str) -> Vec<String> {
+    text.strip_prefix('\u{feff}')
+        .unwrap_or(text)
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Load the commands of a `*CommandsFile` key, resolved against the worktree
+fn command_file(
+    cfg_in: &Value,
+    key: &str,
+    worktree: &Worktree,
+    worktree_root: &str,
+    vars: &Variables,
+) -> Result<Vec<String>> {
+    let path = match cfg_in.get(key) {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(p)) => resolve_worktree_path(&expand_variables(p, vars), worktree_root),
+        Some(v) => return Err(ConfigError::new(key, "not a path").value(v).into()),
+    };
+    let text = worktree
+        .read_text_file(worktree_relative(&path, worktree_root))
+        .map_err(|e| format!("failed to read `{}` {}: {}", key, path, e))?;
+    Ok(parse_command_file(&text))
+}
+
+/// A user command with variables expanded and placeholders filled in
+fn prepare_command(cmd: &str, vars: &Variables, placeholders: &Placeholders) -> String {
+    substitute_placeholders(&expand_variables(cmd, vars), placeholders)
+}
+
+/// A user command array with variables expanded and placeholders filled in
+fn user_commands(cmds: &[String], vars: &Variables, placeholders: &Placeholders) -> Vec<String> {
+    cmds.iter().map(|c| prepare_command(c, vars, placeholders)).collect()
+}
+
+/// Resolve a possibly relative path against the worktree root
+fn resolve_worktree_path(path: &str, worktree_root: &str) -> String {
+    if path.starts_with('/') || worktree_root.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", worktree_root.trim_end_matches('/'), path)
+    }
+}
+
+/// Check that a file exists, going through the worktree since the extension
+/// has no direct filesystem access
+fn worktree_file_exists(worktree: &Worktree, worktree_root: &str, path: &str) -> bool {
+    worktree
+        .read_text_file(worktree_relative(path, worktree_root))
+        .is_ok()
+}
+
+/// Path relative to the worktree root if it lies inside it, else unchanged
+fn worktree_relative<'a>(path: &'a str, worktree_root: &str) -> &'a str {
+    path.strip_prefix(worktree_root)
+        .map(|p| p.trim_start_matches('/'))
+        .unwrap_or(path)
+}
+
+/// Work out which project `.lldbinit` to source, if any
+fn resolve_lldbinit(
+    cfg_in: &Value,
+    worktree: &Worktree,
+    worktree_root: &str,
+    vars: &Variables,
+) -> Result<Option<String>> {
+    let path = match cfg_in.get("lldbinit") {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => return Ok(None),
+        Some(Value::Bool(true)) => resolve_worktree_path(".lldbinit", worktree_root),
+        Some(Value::String(p)) => resolve_worktree_path(&expand_variables(p, vars), worktree_root),
+        Some(v) => {
+            return Err(ConfigError::new("lldbinit", "not true or a path").value(v).into());
+        }
+    };
+
+    // The key was set explicitly, so a missing file is a configuration error
+    if !worktree_file_exists(worktree, worktree_root, &path) {
+        return Err(format!("`lldbinit` file not found: {}", path));
+    }
+    Ok(Some(path))
+}
+
+/// Path of the `profileName` init file, `.zed/lldb-profiles/<name>.lldbinit`
+/// in the worktree. A profile without a file is fine; `None` then.
+fn resolve_profile(
+    cfg_in: &Value,
+    worktree: &Worktree,
+    worktree_root: &str,
+    vars: &Variables,
+    notes: &mut Vec<String>,
+) -> Result<Option<String>> {
+    let name = match cfg_in.get("profileName") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(name)) => expand_variables(name, vars),
+        Some(v) => return Err(ConfigError::new("profileName", "not a name").value(v).into()),
+    };
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(ConfigError::new("profileName", "is a path")
+            .value(format!("{:?}", name))
+            .hint("expected the name of a file in .zed/lldb-profiles")
+            .into());
+    }
+    let path = resolve_worktree_path(
+        &expand_variables(&format!(".zed/lldb-profiles/{}.lldbinit", name), vars),
+        worktree_root,
+    );
+    if !worktree_file_exists(worktree, worktree_root, &path) {
+        notes.push(format!("profile {:?} has no init file at {}", name, path));
+        return Ok(None);
+    }
+    notes.push(format!("profile {:?}: sourcing {}", name, path));
+    Ok(Some(path))
+}
+
+/// Oldest LLVM release that ships the adapter as `lldb-dap`
+const MIN_LLDB_DAP_VERSION: u32 = 18;
+
+/// Parse the major version from `lldb-dap --version` output such as
+/// `lldb version 20.1.2` or `lldb-dap version 18.1.8 (https://...)`
+fn parse_lldb_version(output: &str) -> Option<u32> {
+    let (_, rest) = output.split_once("version ")?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// Binary used when the user does not pin an lldb-dap version
+const DEFAULT_ADAPTER_COMMAND: &str = "lldb-dap-20"; // or "lldb-dap" if you symlinked
+
+/// Finds the adapter the way the worktree would run it
+trait AdapterLookup {
+    /// The path of `command` on the worktree's PATH
+    fn which(&self, command: &str) -> Option<String>;
+    /// Whether `command --version` runs on the host
+    fn runs(&self, command: &str) -> bool;
+}
+
+impl AdapterLookup for Worktree {
+    fn which(&self, command: &str) -> Option<String> {
+        Worktree::which(self, command)
+    }
+
+    fn runs(&self, command: &str) -> bool {
+        zed::process::Command::new(command)
+            .arg("--version")
+            .envs(self.shell_env())
+            .output()
+            .is_ok_and(|out| out.status == Some(0))
+    }
+}
+
+/// Pick the lldb-dap binary to spawn: `adapterPath` if set, else honouring
+/// `lldbDapVersion` if set
+fn resolve_adapter_command(
+    cfg_in: &Value,
+    worktree: &impl AdapterLookup,
+    vars: &Variables,
+    worktree_root: &str,
+    warnings: &mut Vec<String>,
+) -> Result<String> {
+    match (cfg_in.get("adapterPath"), cfg_in.get("lldbDapVersion")) {
+        (None | Some(Value::Null), _) => {}
+        (Some(Value::String(path)), None | Some(Value::Null)) if !path.is_empty() => {
+            return Ok(resolve_worktree_path(&expand_variables(path, vars), worktree_root));
+        }
+        (Some(Value::String(_)), Some(_)) => {
+            return Err("`adapterPath` and `lldbDapVersion` cannot both be set".to_string());
+        }
+        (Some(v), _) => {
+            return Err(ConfigError::new("adapterPath", "not a path")
+                .value(v)
+                .hint("expected the lldb-dap binary, e.g. \"/usr/bin/lldb-dap\"")
+                .into());
+        }
+    }
+    let version = match cfg_in.get("lldbDapVersion") {
+        // Prefer the worktree's PATH (nix shells, project toolchains), then the
+        // host's, as long as the binary there runs
+        None | Some(Value::Null) => {
+            if let Some(path) = worktree.which(DEFAULT_ADAPTER_COMMAND) {
+                return Ok(path);
+            }
+            if worktree.runs(DEFAULT_ADAPTER_COMMAND) {
+                return Ok(DEFAULT_ADAPTER_COMMAND.to_string());
             }
+            return Err(format!(
+                "{} not found in PATH; set `adapterPath` to the lldb-dap binary, or `lldbDapVersion` to use another version",
+                DEFAULT_ADAPTER_COMMAND
+            ));
         }
+        Some(v) => v.as_u64().filter(|n| *n > 0).ok_or_else(|| {
+            ConfigError::new("lldbDapVersion", "not a major version number").value(v).hint("expected e.g. 18")
+        })?,
+    };
 
-        // Add initCommands to config if we have any
-        if !init_cmds.is_empty() {
-            if let Some(obj) = cfg_out.as_object_mut() {
-                obj.insert("initCommands".into(), serde_json::json!(init_cmds));
+    // Only the pinned version and the generic symlink are considered
+    let versioned = format!("lldb-dap-{}", version);
+    if let Some(path) = worktree.which(&versioned) {
+        return Ok(path);
+    }
+    if let Some(path) = worktree.which("lldb-dap") {
+        warnings.push(format!(
+            "{} not found in PATH; using {}, which may be a different LLVM version",
+            versioned, path
+        ));
+        return Ok(path);
+    }
+    Err(format!(
+        "{} not found in PATH (also tried lldb-dap); set `adapterPath` to use a binary elsewhere",
+        versioned
+    ))
+}
+
+/// Parse an address given as `0x`-prefixed hex, decimal, or a JSON number
+fn parse_address(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Read `programArgs`/`args` as an array of arguments, or a string split on
+/// whitespace, expanding variables in each
+fn program_arguments(value: &Value, key: &str, vars: &Variables) -> Result<Vec<String>> {
+    match value {
+        Value::Null => Ok(Vec::new()),
+        Value::String(args) => Ok(args
+            .split_whitespace()
+            .map(|a| expand_variables(a, vars))
+            .collect()),
+        Value::Array(args) => args
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                a.as_str()
+                    .map(|a| expand_variables(a, vars))
+                    .ok_or_else(|| ConfigError::new(format!("{}[{}]", key, i), "not a string")
+                        .value(a)
+                        .to_string())
+            })
+            .collect(),
+        v => Err(ConfigError::new(key, "not an array of strings").value(v).into()),
+    }
+}
+
+/// Translate `loadOffset` or `sectionLoadAddresses` into a `target modules load`
+/// for firmware that runs somewhere other than its link addresses
+fn load_address_commands(cfg_in: &Value, program: Option<&str>) -> Result<Vec<String>> {
+    let offset = cfg_in.get("loadOffset").filter(|v| !v.is_null());
+    let sections = cfg_in.get("sectionLoadAddresses").filter(|v| !v.is_null());
+    let args = match (offset, sections) {
+        (None, None) => return Ok(Vec::new()),
+        (Some(_), Some(_)) => {
+            return Err("`loadOffset` and `sectionLoadAddresses` cannot both be set".to_string());
+        }
+        (Some(offset), None) => {
+            let slide = parse_address(offset).ok_or_else(|| {
+                ConfigError::new("loadOffset", "not an address")
+                    .value(offset)
+                    .hint("expected 0x hex or decimal")
+            })?;
+            vec!["--slide".to_string(), format!("{:#x}", slide)]
+        }
+        (None, Some(sections)) => {
+            let sections = sections.as_object().ok_or_else(|| {
+                ConfigError::new("sectionLoadAddresses", "not an object")
+                    .value(sections)
+                    .hint("expected {\".text\": \"0x...\", ...}")
+            })?;
+            if sections.is_empty() {
+                return Ok(Vec::new());
             }
+            sections
+                .iter()
+                .map(|(name, address)| {
+                    parse_address(address)
+                        .map(|a| [command::quote(name), format!("{:#x}", a)])
+                        .ok_or_else(|| {
+                            ConfigError::new(format!("sectionLoadAddresses.{}", name), "not an address")
+                                .value(address)
+                                .hint("expected 0x hex or decimal")
+                                .to_string()
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?
+                .concat()
         }
+    };
+    let program = program.ok_or_else(|| {
+        "`loadOffset` and `sectionLoadAddresses` need a `program` to load".to_string()
+    })?;
+    let cmd = CommandBuilder::new("target modules load").word("--file").arg(program);
+    Ok(vec![args.iter().fold(cmd, |cmd, a| cmd.word(a)).build()])
+}
 
-        Ok(DebugAdapterBinary {
-            command: Some("lldb-dap-20".to_string()), // or "lldb-dap" if you symlinked
-            arguments: vec![],
-            cwd: None,
-            envs,
-            request_args: StartDebuggingRequestArguments {
-                configuration: cfg_out.to_string(),
-                request,
+/// Translate `watchpoints` entries into `watchpoint set` commands
+fn watchpoint_commands(cfg_in: &Value) -> Result<Vec<String>> {
+    let Some(watchpoints) = cfg_in.get("watchpoints") else {
+        return Ok(Vec::new());
+    };
+    let watchpoints = watchpoints
+        .as_array()
+        .ok_or_else(|| ConfigError::new("watchpoints", "not an array").value(watchpoints))?;
+
+    let mut cmds = Vec::new();
+    for (i, wp) in watchpoints.iter().enumerate() {
+        let address = wp
+            .get("address")
+            .ok_or_else(|| ConfigError::new(format!("watchpoints[{}].address", i), "is missing"))?;
+        let watch_type = match wp.get("type").and_then(|v| v.as_str()).unwrap_or("write") {
+            "read" => "read",
+            "write" => "write",
+            "readwrite" => "read_write",
+            other => {
+                return Err(ConfigError::new(format!("watchpoints[{}].type", i), "unknown watchpoint type")
+                    .value(format!("{:?}", other))
+                    .hint("expected read, write or readwrite")
+                    .into());
+            }
+        };
+        let size = match wp.get("size") {
+            None => None,
+            Some(v) => match v.as_u64() {
+                Some(n @ (1 | 2 | 4 | 8)) => Some(n),
+                _ => {
+                    return Err(ConfigError::new(format!("watchpoints[{}].size", i), "unsupported size")
+                        .value(v)
+                        .hint("expected 1, 2, 4 or 8")
+                        .into());
+                }
             },
-            connection: None,
-        })
+        };
+        let with_size = |cmd: CommandBuilder| match size {
+            Some(n) => cmd.word("-s").word(n),
+            None => cmd,
+        };
+
+        if let Some(addr) = parse_address(address) {
+            let cmd = with_size(CommandBuilder::new("watchpoint set expression").word("-w").word(watch_type));
+            cmds.push(cmd.word("--").word(format!("{:#x}", addr)).build());
+        } else if let Some(name) = address.as_str().filter(|s| !s.is_empty()) {
+            // Not a literal address; treat it as a variable name
+            let cmd = with_size(CommandBuilder::new("watchpoint set variable").word("-w").word(watch_type));
+            cmds.push(cmd.arg(name).build());
+        } else {
+            return Err(ConfigError::new(format!("watchpoints[{}].address", i), "not an address or a name")
+                .value(address)
+                .hint("expected e.g. \"0x20001000\" or a variable name")
+                .into());
+        }
     }
+    Ok(cmds)
 }
 
-zed::register_extension!(Ext);
+/// Translate `monitorCommands` (and `resetAfterConnect`) into monitor packets
+/// for the stub, e.g. OpenOCD's `reset halt`
+fn monitor_commands(cfg_in: &Value, flags: &RemoteDebugConfig) -> Vec<String> {
+    let mut monitor = Vec::new();
+    if cfg_in.get("resetAfterConnect").and_then(|v| v.as_bool()) == Some(true) {
+        monitor.push("reset halt".to_string());
+    }
+    for cmd in flags.monitor_commands.iter().flatten().map(|c| c.trim()) {
+        // `resetAfterConnect` already covers an explicit reset halt
+        if !cmd.is_empty() && !monitor.iter().any(|m| m == cmd) {
+            monitor.push(cmd.to_string());
+        }
+    }
+    monitor
+        .iter()
+        .map(|cmd| format!("process plugin packet monitor {}", cmd))
+        .collect()
+}
+
+/// Translate `breakpoints` entries into `breakpoint set` commands
+fn breakpoint_commands(
+    cfg_in: &Value,
+    vars: &Variables,
+    worktree_root: &str,
+) -> Result<Vec<String>> {
+    let Some(breakpoints) = cfg_in.get("breakpoints") else {
+        return Ok(Vec::new());
+    };
+    let breakpoints = breakpoints
+        .as_array()
+        .ok_or_else(|| ConfigError::new("breakpoints", "not an array").value(breakpoints))?;
+
+    let mut cmds = Vec::new();
+    for (i, bp) in breakpoints.iter().enumerate() {
+        if let Some(function) = bp.get("function") {
+            let function = function
+                .as_str()
+                .filter(|f| !f.is_empty())
+                .ok_or_else(|| ConfigError::new(format!("breakpoints[{}].function", i), "not a name"))?;
+            cmds.push(CommandBuilder::new("breakpoint set").word("--name").arg(function).build());
+            continue;
+        }
+
+        let file = bp
+            .get("file")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("`breakpoints[{}]` needs either `file` and `line`, or `function`", i))?;
+        let line = bp
+            .get("line")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .ok_or_else(|| ConfigError::new(format!("breakpoints[{}].line", i), "not a positive integer"))?;
+        let file = resolve_worktree_path(&expand_variables(file, vars), worktree_root);
+        cmds.push(
+            CommandBuilder::new("breakpoint set")
+                .word("--file")
+                .arg(&file)
+                .word("--line")
+                .word(line)
+                .build(),
+        );
+    }
+    Ok(cmds)
+}
+
+/// `launchCommands`: `create`, the `target create` for `program`, then the
+/// user's commands. A session has one target, so `create` is left out when
+/// the user's commands make their own.
+fn launch_commands(create: Option<String>, user_cmds: Vec<String>) -> Vec<String> {
+    let creates_target = user_cmds
+        .iter()
+        .any(|c| command::split(c).starts_with(&["target".to_string(), "create".to_string()]));
+    create.filter(|_| !creates_target).into_iter().chain(user_cmds).collect()
+}
+
+/// The attach sequence around connecting. The target is normally created
+/// first, so LLDB has the program's symbols at the first stop. In
+/// extended-remote mode the stub starts the program after LLDB has connected,
+/// so the target comes only then and module addresses come from the stub.
+fn connection_sequence(target_cmds: Vec<String>, connect_cmds: Vec<String>, extended_remote: bool) -> Vec<String> {
+    if extended_remote {
+        connect_cmds.into_iter().chain(target_cmds).collect()
+    } else {
+        target_cmds.into_iter().chain(connect_cmds).collect()
+    }
+}
+
+/// The user's commands for lldb-dap's own lifecycle slots. preRunCommands run
+/// after the target is created, just before launching or attaching;
+/// postRunCommands once the launch or attach has completed.
+fn run_command_fields(flags: &RemoteDebugConfig, vars: &Variables, placeholders: &Placeholders) -> Vec<(String, Value)> {
+    ["preRunCommands", "postRunCommands"]
+        .into_iter()
+        .map(|key| (key, user_commands(flags.commands(key), vars, placeholders)))
+        .filter(|(_, cmds)| !cmds.is_empty())
+        .map(|(key, cmds)| (key.to_string(), serde_json::json!(cmds)))
+        .collect()
+}
+
+/// lldb-dap pipes the program's stdio by default; with `disableStdioRedirect`
+/// a launch runs in a terminal instead, which gives it a real TTY
+fn terminal_field(
+    flags: &RemoteDebugConfig,
+    request: StartDebuggingRequestArgumentsRequest,
+    warnings: &mut Vec<String>,
+) -> Option<(&'static str, Value)> {
+    if !flags.disable_stdio_redirect {
+        return None;
+    }
+    if request != StartDebuggingRequestArgumentsRequest::Launch {
+        warnings.push("disableStdioRedirect only applies to launch requests".to_string());
+        return None;
+    }
+    Some(("runInTerminal", Value::Bool(true)))
+}
+
+/// What runs once connected. Monitor commands such as a reset come first, so
+/// the watch- and breakpoints after them are set on the freshly reset core.
+fn post_connect_commands(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    kernel: Option<&kernel::Kernel>,
+) -> Result<Vec<String>> {
+    let mut cmds = monitor_commands(cfg_in, flags);
+
+    // Kernel modules are only where the running kernel put them
+    if let Some(kernel) = kernel {
+        cmds.extend(kernel.post_connect_commands());
+    }
+
+    // Shows where the process stopped, confirming the connection in the debug console
+    if flags.verify_connection {
+        cmds.push("process status".to_string());
+    }
+    Ok(cmds)
+}
+
+/// An architecture the program file doesn't settle, e.g. a universal binary
+fn architecture(cfg_in: &Value) -> Result<Option<String>> {
+    match cfg_in.get("architecture") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(arch)) if !arch.trim().is_empty() => Ok(Some(arch.trim().to_string())),
+        Some(v) => Err(ConfigError::new("architecture", "not an architecture name")
+            .value(v)
+            .hint("expected e.g. \"arm64\" or \"x86_64\"")
+            .into()),
+    }
+}
+
+/// `target create`, for `architecture` if given; the program is added by the caller
+fn target_create(architecture: Option<&str>) -> CommandBuilder {
+    match architecture {
+        Some(arch) => CommandBuilder::new("target create").word("--arch").arg(arch),
+        None => CommandBuilder::new("target create"),
+    }
+}
+
+/// Have an extended-remote stub run `remoteProgram`. `main` needs no
+/// `process continue` since the launch runs to it.
+fn remote_launch_commands(stop_on_entry: bool, break_main: bool, program_args: &[String]) -> Vec<String> {
+    let mut cmds = Vec::new();
+    if stop_on_entry && break_main {
+        cmds.push("breakpoint set --one-shot true --name main".to_string());
+    }
+    let mut launch = CommandBuilder::new("process launch");
+    if stop_on_entry && !break_main {
+        launch = launch.word("--stop-at-entry");
+    }
+    if !program_args.is_empty() {
+        launch = program_args.iter().fold(launch.word("--"), |cmd, arg| cmd.arg(arg));
+    }
+    cmds.push(launch.build());
+    cmds
+}
+
+/// Whether to detach from the debuggee (`true`) or kill it (`false`) when the
+/// session ends, from `detachOnExit` or its inverse `terminateDebuggee`.
+/// Attaching detaches by default: the process may be someone's service.
+fn detach_on_exit(cfg_in: &Value, request: StartDebuggingRequestArgumentsRequest) -> Result<Option<bool>> {
+    let detach = match (cfg_in.get("detachOnExit"), cfg_in.get("terminateDebuggee")) {
+        (None | Some(Value::Null), None | Some(Value::Null)) => None,
+        (Some(v), None | Some(Value::Null)) => Some(v.as_bool().ok_or_else(|| {
+            ConfigError::new("detachOnExit", "not a boolean").value(v)
+        })?),
+        (None | Some(Value::Null), Some(v)) => Some(!v.as_bool().ok_or_else(|| {
+            ConfigError::new("terminateDebuggee", "not a boolean").value(v)
+        })?),
+        (Some(_), Some(_)) => {
+            return Err("`detachOnExit` and `terminateDebuggee` cannot both be set".to_string());
+        }
+    };
+    Ok(detach.or((request == StartDebuggingRequestArgumentsRequest::Attach).then_some(true)))
+}
+
+/// lldb-dap's `exitCommands`: the user's, then on the command-driven path
+/// (attachCommands or launchCommands) `detach` or `process kill` for
+/// `detachOnExit`, unless the user's already end the session that way
+fn exit_commands(
+    flags: &RemoteDebugConfig,
+    detach: Option<bool>,
+    command_driven: bool,
+    vars: &Variables,
+    placeholders: &Placeholders,
+) -> Vec<String> {
+    let mut cmds = user_commands(flags.commands("exitCommands"), vars, placeholders);
+    if let Some(detach) = detach
+        && command_driven
+    {
+        let command = if detach { "detach" } else { "process kill" };
+        if !cmds.iter().any(|c| command::normalize(c) == command::normalize(command)) {
+            cmds.push(command.to_string());
+        }
+    }
+    cmds
+}
+
+/// Drop repeats of a command, keeping the first. Lines that differ only in
+/// quoting or spacing are the same command to LLDB.
+fn dedup_commands(cmds: &mut Vec<String>) {
+    let mut seen = HashSet::new();
+    cmds.retain(|c| seen.insert(command::normalize(c)));
+}
+
+/// `target.source-map` entries for `pathMappings`, most specific prefix first,
+/// then with `reverse_all` each the other way round. Each is appended, as
+/// `settings set` would replace the ones before it, the user's included.
+/// Mappings `init_cmds` already set, however quoted, are left out.
+fn source_map_commands(
+    mappings: Option<&[PathMappingEntry]>,
+    vars: &Variables,
+    reverse_all: bool,
+    init_cmds: &[String],
+    notes: &mut Vec<String>,
+) -> Vec<String> {
+    let Some(mappings) = mappings else {
+        notes.push("no pathMappings: skipping source-map".to_string());
+        return Vec::new();
+    };
+    let mut source_map = Vec::new();
+    for mapping in mappings {
+        // Expand common variables in paths; a trailing slash makes LLDB mis-match prefixes
+        let remote = normalize_path_trailing_slash(&normalize_remote_path(&expand_variables(&mapping.remote_root, vars)));
+        let local = normalize_path_trailing_slash(&wsl::to_linux(&expand_variables(&mapping.local_root, vars)));
+        // Sources already where the debug info says; mapping them to themselves only confuses LLDB
+        if remote == local {
+            continue;
+        }
+        // Reversed mappings are for trees synced the other way
+        source_map.push(if mapping.reverse_source_map == Some(true) {
+            PathMapping { remote: local, local: remote }
+        } else {
+            PathMapping { remote, local }
+        });
+    }
+    // Most specific prefixes first, whatever order they were written in
+    source_map.sort();
+    notes.push(format!(
+        "{} of {} pathMappings need a source-map entry",
+        source_map.len(),
+        mappings.len()
+    ));
+    // `reverseSourceMap` adds every mapping the other way round too, after them
+    let mut reversed: Vec<PathMapping> = Vec::new();
+    if reverse_all {
+        reversed = source_map
+            .iter()
+            .map(|m| PathMapping {
+                remote: m.local.clone(),
+                local: m.remote.clone(),
+            })
+            .collect();
+        reversed.sort();
+    }
+    // The user may have written the same mapping in initCommands
+    let written: Vec<PathMapping> = init_cmds.iter().flat_map(|c| source_map_entries(c)).collect();
+    source_map
+        .iter()
+        .chain(&reversed)
+        .filter(|m| !written.contains(m))
+        .map(|m| {
+            CommandBuilder::new("settings append target.source-map")
+                .arg(&m.remote)
+                .arg(&m.local)
+                .build()
+        })
+        .collect()
+}
+
+/// The mappings a `settings set` or `settings append` of `target.source-map`
+/// adds, with trailing slashes dropped as in generated ones; none for other commands
+fn source_map_entries(cmd: &str) -> Vec<PathMapping> {
+    let words = command::split(cmd);
+    match words.as_slice() {
+        [settings, op, key, pairs @ ..]
+            if settings == "settings" && (op == "set" || op == "append") && key == "target.source-map" =>
+        {
+            pairs
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .map(|[remote, local]| PathMapping {
+                    remote: normalize_path_trailing_slash(remote),
+                    local: normalize_path_trailing_slash(local),
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// `target.source-map` entries mapping each `stripPathPrefix` to the worktree
+/// root, for absolute build-machine paths baked into the debug info. LLDB
+/// rejects a replacement that doesn't exist, the empty one included, so the
+/// paths are made relative to the worktree rather than to nothing.
+fn strip_prefix_commands(cfg_in: &Value, vars: &Variables, worktree_root: &str) -> Result<Vec<String>> {
+    let prefixes = match cfg_in.get("stripPathPrefix") {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(prefix)) => vec![prefix.as_str()],
+        Some(Value::Array(prefixes)) => prefixes
+            .iter()
+            .map(|p| {
+                p.as_str().ok_or_else(|| {
+                    ConfigError::new("stripPathPrefix", "has an entry that is not a path")
+                        .value(p)
+                        .to_string()
+                })
+            })
+            .collect::<Result<_>>()?,
+        Some(v) => {
+            return Err(ConfigError::new("stripPathPrefix", "not a path or an array of paths")
+                .value(v)
+                .into());
+        }
+    };
+    // Without a worktree, relative to lldb-dap's working directory
+    let root = if worktree_root.is_empty() { "." } else { worktree_root };
+    Ok(prefixes
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            // `append`: `set` would replace the mappings before it
+            CommandBuilder::new("settings append target.source-map")
+                .arg(&normalize_path_trailing_slash(&expand_variables(p, vars)))
+                .arg(root)
+                .build()
+        })
+        .collect())
+}
+
+/// Check that every `pathMappings[].localRoot` is an existing directory.
+/// Remote roots can't be checked from here.
+fn validate_path_mappings(mappings: &[PathMappingEntry], vars: &Variables, worktree_root: &str) -> Result<()> {
+    let mut missing = Vec::new();
+    for (i, mapping) in mappings.iter().enumerate() {
+        let local = resolve_worktree_path(
+            &normalize_path_trailing_slash(&expand_variables(&mapping.local_root, vars)),
+            worktree_root,
+        );
+        if !host::is_dir(&local)? {
+            missing.push(format!("pathMappings[{}].localRoot: {}", i, local));
+        }
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "local source roots do not exist (unset `validatePathMappings` if they are mounted later):\n  {}",
+            missing.join("\n  ")
+        ))
+    }
+}
+
+/// Turn a warning into an LLDB command so it shows up in the debug console
+fn warning_command(message: &str) -> String {
+    note_command(&format!("warning: {}", message))
+}
+
+/// Run the `connect` command up to `attempts` times through LLDB's Python,
+/// stopping at the first success, then show the last attempt's output or error.
+/// Only retries wait, half a second each.
+fn retry_commands(connect: &str, address: &str, attempts: u64) -> Vec<String> {
+    // JSON string literals are also valid Python string literals
+    let failed = serde_json::json!(format!(
+        "lldb-remote: gdb-remote to {} failed after {} attempts:",
+        address, attempts
+    ));
+    vec![
+        "script lldb_remote_tries = []".to_string(),
+        format!(
+            "script lldb_remote_connected = any((lldb_remote_tries.append(lldb.SBCommandReturnObject()), i and __import__(\"time\").sleep(0.5), lldb.debugger.GetCommandInterpreter().HandleCommand({}, lldb_remote_tries[-1]), lldb_remote_tries[-1].Succeeded())[-1] for i in range({}))",
+            serde_json::json!(connect),
+            attempts
+        ),
+        format!(
+            "script lldb_remote_shown = print(lldb_remote_tries[-1].GetOutput(), end=\"\") if lldb_remote_connected else print({}, lldb_remote_tries[-1].GetError().strip())",
+            failed
+        ),
+    ]
+}
+
+/// An LLDB command that prints `message` to the debug console
+fn note_command(message: &str) -> String {
+    // A JSON string literal is also a valid Python string literal
+    format!(
+        "script print({})",
+        serde_json::json!(format!("lldb-remote: {}", message))
+    )
+}
+
+/// The error a `dryRun` session fails with: the adapter it would have started, as JSON
+fn dry_run_report(binary: &DebugAdapterBinary) -> String {
+    let configuration: Value = serde_json::from_str(&binary.request_args.configuration)
+        .unwrap_or_else(|_| Value::String(binary.request_args.configuration.clone()));
+    let mut report = serde_json::json!({
+        "command": binary.command,
+        "arguments": binary.arguments,
+        "envs": binary
+            .envs
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect::<serde_json::Map<_, _>>(),
+        "configuration": configuration,
+    });
+    if let Some(cwd) = &binary.cwd {
+        report["cwd"] = Value::String(cwd.clone());
+    }
+    let report = serde_json::to_string_pretty(&report).unwrap_or_else(|_| report.to_string());
+    format!("DRY_RUN: {}", report)
+}
+
+/// The variables paths and commands are expanded with. The home directory
+/// comes from the worktree path, else from the shell environment.
+fn session_variables(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    worktree_root: &str,
+    shell_env: Vec<(String, String)>,
+) -> Result<Variables> {
+    let home = match infer_home_from_path(worktree_root) {
+        home if home.is_empty() => shell_env
+            .iter()
+            .find(|(k, _)| k == "HOME")
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default(),
+        home => home,
+    };
+    let shell_timeout_secs = match cfg_in.get("shellExpansionTimeout") {
+        None | Some(Value::Null) => DEFAULT_SHELL_EXPANSION_TIMEOUT_SECS,
+        Some(v) => v.as_u64().filter(|t| *t > 0).ok_or_else(|| {
+            ConfigError::new("shellExpansionTimeout", "not a positive number of seconds").value(v)
+        })?,
+    };
+    Ok(Variables {
+        home,
+        target_triple: target_triple(cfg_in)?,
+        allow_shell_commands: flags.allow_shell_expansion,
+        shell_env,
+        shell_timeout_secs,
+        shell_warnings: RefCell::new(Vec::new()),
+    })
+}
+
+/// Config keys of the debug servers the extension can start
+const SERVER_BLOCKS: [&str; 6] = ["server", "qemu", "valgrind", "rr", "docker", "android"];
+
+/// A debug server the extension starts on this machine for the session,
+/// either described in full or as qemu-user or valgrind serving the program,
+/// with the key it came from
+fn local_server(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    request: StartDebuggingRequestArgumentsRequest,
+    vars: &Variables,
+    worktree_root: &str,
+) -> Result<Option<(&'static str, server::Server)>> {
+    let blocks: Vec<&str> = SERVER_BLOCKS
+        .into_iter()
+        .filter(|key| cfg_in.get(*key).is_some())
+        .collect();
+    if blocks.len() > 1 {
+        return Err(format!("only one of {} can be set", blocks.join(", ")));
+    }
+    let Some(block) = blocks.first().copied() else {
+        return Ok(None);
+    };
+    if block == "server" {
+        return Ok(Some((block, server::parse(&cfg_in["server"], vars, worktree_root)?)));
+    }
+    // These run a server for the program and connect to it themselves
+    if flags.target.is_some() || flags.connection.is_some() {
+        return Err(format!(
+            "`{}` runs its own server, so `target` and `connection` cannot be set",
+            block
+        ));
+    }
+    if request == StartDebuggingRequestArgumentsRequest::Launch {
+        return Err(format!("`{}` needs \"request\": \"attach\"", block));
+    }
+    let server = match block {
+        "qemu" => server::qemu(&cfg_in["qemu"], vars)?,
+        "valgrind" => server::valgrind(&cfg_in["valgrind"], vars)?,
+        "rr" => server::rr(&cfg_in["rr"], vars)?,
+        "android" => server::android(&cfg_in["android"], vars)?,
+        _ => {
+            // A process name is looked up in the container's process list
+            let mut docker = cfg_in["docker"].clone();
+            if let Some(name) = docker
+                .get("pid")
+                .and_then(|p| p.as_str())
+                .filter(|p| p.parse::<u32>().is_err())
+            {
+                let pid = process_list::find_pid(&process_list::ShellCommandProcessListProvider, cfg_in, name)?;
+                docker["pid"] = serde_json::json!(pid);
+            }
+            server::docker(&docker, vars)?
+        }
+    };
+    Ok(Some((block, server)))
+}
+
+/// A Windows named pipe (\\server\pipe\name) instead of a TCP target, for
+/// networks that block the port but allow SMB. The extension itself runs as
+/// WASM, so the host platform comes from Zed rather than `cfg!`.
+fn named_pipe(cfg_in: &Value) -> Result<Option<String>> {
+    match cfg_in.get("namedPipe") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(pipe)) if !pipe.is_empty() => {
+            if zed::current_platform().0 != zed::Os::Windows {
+                return Err("`namedPipe` is only supported on Windows".to_string());
+            }
+            if let Some(key) = ["target", "connection"]
+                .into_iter()
+                .chain(SERVER_BLOCKS)
+                .find(|key| cfg_in.get(*key).is_some())
+            {
+                return Err(format!("`namedPipe` and `{}` cannot both be set", key));
+            }
+            Ok(Some(pipe.clone()))
+        }
+        Some(v) => Err(ConfigError::new("namedPipe", "not a pipe path")
+            .value(v)
+            .hint("expected e.g. \\\\server\\pipe\\lldb-debug")
+            .into()),
+    }
+}
+
+/// The address of a stub that picks its own port and logs it (`portFromFile`)
+fn port_file_address(
+    cfg_in: &Value,
+    has_server: bool,
+    worktree: &Worktree,
+    worktree_root: &str,
+    vars: &Variables,
+) -> Result<Option<String>> {
+    let Some(spec) = cfg_in.get("portFromFile").filter(|v| !v.is_null()) else {
+        return Ok(None);
+    };
+    if let Some(key) = ["target", "connection", "namedPipe"]
+        .into_iter()
+        .find(|key| cfg_in.get(*key).is_some())
+    {
+        return Err(format!("`portFromFile` and `{}` cannot both be set", key));
+    }
+    if has_server {
+        return Err("`portFromFile` cannot be combined with a server the session starts".to_string());
+    }
+    Ok(Some(target_from_port_file(spec, worktree, worktree_root, vars)?))
+}
+
+/// The stubs to connect to, first the one that stays selected. They come from
+/// `target` (tcp://HOST:PORT or a bare HOST:PORT) or a structured `connection`,
+/// else from `address`, that of a local server or a port file. Only attach
+/// needs one.
+fn session_targets(
+    flags: &RemoteDebugConfig,
+    request: StartDebuggingRequestArgumentsRequest,
+    address: Option<String>,
+    server_port: Option<u16>,
+    has_named_pipe: bool,
+    vars: &Variables,
+) -> Result<Vec<Target>> {
+    let with_server_port = |t: &str| match server_port {
+        Some(port) => t.replace("${serverPort}", &port.to_string()),
+        None => t.to_string(),
+    };
+    let targets = match (&flags.target, &flags.connection, address) {
+        (Some(_), Some(_), _) => {
+            return Err("`target` and `connection` cannot both be set".to_string());
+        }
+        (None, None, Some(address)) => vec![parse_target(&address)?],
+        (None, None, None) if request == StartDebuggingRequestArgumentsRequest::Launch || has_named_pipe => {
+            Vec::new()
+        }
+        (None, Some(connection), _) => {
+            vec![parse_target(&target_from_connection(connection)).map_err(|e| e.at("connection"))?]
+        }
+        // Multi-core boards expose one stub per core; connect to each in order
+        (Some(OneOrMany::Many(entries)), None, _) => {
+            if entries.is_empty() {
+                return Err(ConfigError::new("target", "is an empty array")
+                    .hint("expected one or more tcp://HOST:PORT")
+                    .into());
+            }
+            entries
+                .iter()
+                .enumerate()
+                .map(|(i, target)| {
+                    parse_target(&expand_variables(&with_server_port(target), vars))
+                        .map_err(|e| e.at(format!("target[{}]", i)))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        }
+        (None, None, None) => {
+            return Err(ConfigError::new("target", "is missing").hint("expected tcp://HOST:PORT").into());
+        }
+        (Some(OneOrMany::One(target)), None, _) => {
+            vec![parse_target(&expand_variables(&with_server_port(target), vars))?]
+        }
+    };
+    // TLS is a global setting, so every connection must agree on it
+    if targets.windows(2).any(|w| w[0].tls != w[1].tls) {
+        return Err("`target` mixes tcp:// and tcps:// addresses".to_string());
+    }
+    Ok(targets)
+}
+
+/// TLS settings for a tcps:// target
+fn tls_commands(cfg_in: &Value, vars: &Variables, warnings: &mut Vec<String>) -> Vec<String> {
+    let mut cmds = vec!["settings set plugin.process.gdb-remote.use-tls true".to_string()];
+    let cert = cfg_in.get("tlsCertFile").and_then(|v| v.as_str());
+    let key = cfg_in.get("tlsKeyFile").and_then(|v| v.as_str());
+    if let Some(cert) = cert {
+        cmds.push(
+            CommandBuilder::new("settings set plugin.process.gdb-remote.cert")
+                .arg(&expand_variables(cert, vars))
+                .build(),
+        );
+    }
+    if let Some(key) = key {
+        cmds.push(
+            CommandBuilder::new("settings set plugin.process.gdb-remote.key")
+                .arg(&expand_variables(key, vars))
+                .build(),
+        );
+    }
+    if cert.is_none() && key.is_none() {
+        warnings.push("tcps:// target without tlsCertFile/tlsKeyFile; TLS will use system CAs only".to_string());
+    }
+    cmds
+}
+
+/// The name of a session whose lldb-dap runs as a server that a later session
+/// can reconnect to
+fn session_id(cfg_in: &Value) -> Result<Option<String>> {
+    match cfg_in.get("sessionId") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(id)) if !id.is_empty() => Ok(Some(id.clone())),
+        Some(v) => Err(ConfigError::new("sessionId", "not a non-empty string").value(v).into()),
+    }
+}
+
+/// The `kernel` block, which the kernel's own relocation settings replace
+/// `loadOffset` and `sectionLoadAddresses` for
+fn session_kernel(cfg_in: &Value, vars: &Variables, worktree_root: &str) -> Result<Option<kernel::Kernel>> {
+    let Some(kernel) = cfg_in.get("kernel") else {
+        return Ok(None);
+    };
+    if let Some(key) = ["loadOffset", "sectionLoadAddresses"]
+        .into_iter()
+        .find(|key| cfg_in.get(*key).is_some())
+    {
+        return Err(format!("`kernel` and `{}` cannot both be set (use `kernel.kaslrOffset`)", key));
+    }
+    Ok(Some(kernel::parse(kernel, vars, worktree_root)?))
+}
+
+/// Fail now if the stub at `addrs` or `named_pipe` isn't up, rather than after
+/// the adapter has started. A server the session starts itself isn't running
+/// yet, so it is not checked.
+fn check_stub(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    addrs: &[&str],
+    named_pipe: Option<&str>,
+    has_server: bool,
+    vars: &Variables,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    if let Some(checks) = cfg_in.get("preflight")
+        && !flags.dry_run
+    {
+        if has_server {
+            warnings.push("`preflight` is skipped for a server the session starts".to_string());
+        } else {
+            preflight::run(checks, addrs, &preflight::NetcatProbe)?;
+        }
+    }
+    // A stub started by something else (a CI hook, a deploy) may still be coming up
+    if let Some(wait) = cfg_in.get("waitForServer")
+        && !flags.dry_run
+    {
+        if has_server {
+            warnings.push("`waitForServer` is skipped for a server the session starts".to_string());
+        } else {
+            let endpoints: Vec<preflight::Endpoint> = addrs
+                .iter()
+                .map(|addr| preflight::Endpoint::Tcp(addr))
+                .chain(named_pipe.map(preflight::Endpoint::Path))
+                .collect();
+            preflight::wait_for_server(wait, &endpoints, &preflight::NetcatProbe)?;
+        }
+    }
+    match cfg_in.get("connectionProbeCommand") {
+        None | Some(Value::Null) => {}
+        Some(Value::String(_)) if has_server => {
+            warnings.push("`connectionProbeCommand` is skipped for a server the session starts".to_string())
+        }
+        Some(Value::String(_)) if flags.dry_run => {}
+        Some(Value::String(command)) if !command.is_empty() => {
+            preflight::wait_for_command(cfg_in, &expand_variables(command, vars))?;
+        }
+        Some(v) => {
+            return Err(ConfigError::new("connectionProbeCommand", "not a shell command").value(v).into());
+        }
+    }
+    Ok(())
+}
+
+/// `attachTimeout`. A stub that stops answering would otherwise stall the
+/// attach forever: lldb-dap gives up waiting for the attach commands after
+/// `timeout`, and LLDB on each unanswered packet after the packet timeout.
+fn attach_timeout(cfg_in: &Value) -> Result<Option<u64>> {
+    match cfg_in.get("attachTimeout") {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => Ok(Some(v.as_u64().filter(|n| *n > 0).ok_or_else(|| {
+            ConfigError::new("attachTimeout", "not a positive number of seconds").value(v)
+        })?)),
+    }
+}
+
+/// The longer packet timeout slow targets get while connecting, until the
+/// first stop, in seconds
+fn first_stop_timeout(cfg_in: &Value) -> Result<Option<u64>> {
+    match (cfg_in.get("firstStopTimeout"), cfg_in.get("connectTimeoutMs")) {
+        (None | Some(Value::Null), None | Some(Value::Null)) => Ok(None),
+        (Some(v), None | Some(Value::Null)) => Ok(Some(v.as_u64().filter(|n| *n > 0).ok_or_else(|| {
+            ConfigError::new("firstStopTimeout", "not a positive number of seconds").value(v)
+        })?)),
+        // LLDB's packet timeout is in whole seconds
+        (None | Some(Value::Null), Some(v)) => Ok(Some(
+            v.as_u64()
+                .filter(|n| *n > 0)
+                .map(|ms| ms.div_ceil(1000))
+                .ok_or_else(|| {
+                    ConfigError::new("connectTimeoutMs", "not a positive number of milliseconds").value(v)
+                })?,
+        )),
+        (Some(_), Some(_)) => Err("`firstStopTimeout` and `connectTimeoutMs` cannot both be set".to_string()),
+    }
+}
+
+/// How many times to try `gdb-remote`: a busy board may drop the first
+/// handshake even with the port open
+fn connect_attempts(cfg_in: &Value) -> Result<u64> {
+    match cfg_in.get("connectRetries") {
+        None | Some(Value::Null) => Ok(1),
+        Some(v) => Ok(1 + v.as_u64().ok_or_else(|| {
+            ConfigError::new("connectRetries", "not a non-negative integer").value(v)
+        })?),
+    }
+}
+
+/// Fork handling has to be set before the stub reports the first fork, so
+/// it goes in initCommands. A stub without fork events never reports one,
+/// and the setting is then unused.
+fn fork_commands(cfg_in: &Value, warnings: &mut Vec<String>) -> Result<Vec<String>> {
+    let mut cmds = Vec::new();
+    match cfg_in.get("followForkMode") {
+        None | Some(Value::Null) => {}
+        Some(Value::String(mode)) if mode == "parent" || mode == "child" => {
+            cmds.push(format!("settings set target.process.follow-fork-mode {}", mode));
+        }
+        Some(v) => {
+            return Err(ConfigError::new("followForkMode", "unknown mode")
+                .value(v)
+                .hint("expected \"parent\" or \"child\"")
+                .into());
+        }
+    }
+    match cfg_in.get("detachOnFork") {
+        None | Some(Value::Null) | Some(Value::Bool(true)) => {}
+        Some(Value::Bool(false)) => warnings.push(
+            "`detachOnFork: false` is not supported; LLDB always detaches the process it doesn't follow".to_string(),
+        ),
+        Some(v) => return Err(ConfigError::new("detachOnFork", "not a boolean").value(v).into()),
+    }
+    Ok(cmds)
+}
+
+/// What follows the user's attachCommands, last of all. An `attached` process
+/// is stopped wherever the stub halted it, so `stopOnEntryBreakMain` runs it
+/// to `main` for a predictable first stop.
+fn after_attach_commands(cfg_in: &Value, flags: &RemoteDebugConfig, attached: bool) -> Result<Vec<String>> {
+    let (stop_on_entry, break_main) = (flags.stop_on_entry, flags.stop_on_entry_break_main);
+    let mut cmds = Vec::new();
+    if stop_on_entry && break_main && attached {
+        cmds.push("breakpoint set --one-shot true --name main".to_string());
+        cmds.push("process continue".to_string());
+    }
+    cmds.extend(continue_on_attach_command(cfg_in, stop_on_entry, break_main)?);
+    Ok(cmds)
+}
+
+/// Leave the process running or halted after attaching, whatever the stub did
+fn continue_on_attach_command(cfg_in: &Value, stop_on_entry: bool, break_main: bool) -> Result<Option<String>> {
+    match cfg_in.get("continueOnAttach") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(continue_on_attach)) => {
+            if *continue_on_attach && stop_on_entry {
+                return Err("`continueOnAttach` and `stopOnEntry` cannot both be true".to_string());
+            }
+            if stop_on_entry && break_main {
+                return Err(
+                    "`continueOnAttach` cannot be combined with `stopOnEntryBreakMain`, which already runs to `main`"
+                        .to_string(),
+                );
+            }
+            Ok(Some(if *continue_on_attach {
+                "process continue".to_string()
+            } else {
+                "process interrupt".to_string()
+            }))
+        }
+        Some(v) => Err(ConfigError::new("continueOnAttach", "not a boolean").value(v).into()),
+    }
+}
+
+/// The launchCommands prefix of a restartable session. On a restart lldb-dap
+/// kills the process and runs launchCommands again, so they rebuild and
+/// redeploy first. The guard variable persists in LLDB's Python, which skips
+/// that on the first run: the extension has just done it.
+fn rebuild_commands(
+    cfg_in: &Value,
+    worktree_root: &str,
+    triple: Option<&str>,
+    program: Option<&str>,
+    vars: &Variables,
+) -> Result<Vec<String>> {
+    let mut rebuild = Vec::new();
+    rebuild.extend(cargo::rebuild_command(cfg_in, worktree_root, triple)?);
+    if let Some(deploy) = cfg_in.get("deploy") {
+        rebuild.extend(deploy::shell_commands(deploy, program, vars)?);
+    }
+    if rebuild.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(vec![
+        "script lldb_remote_restarting = \"lldb_remote_restarting\" in globals()".to_string(),
+        "script import subprocess".to_string(),
+        format!(
+            "script lldb_remote_rebuilt = lldb_remote_restarting and [subprocess.run(c, shell=True, check=True, stdin=subprocess.DEVNULL, capture_output=True) for c in {}]",
+            serde_json::to_string(&rebuild).map_err(|e| e.to_string())?
+        ),
+    ])
+}
+
+/// `maxRestarts`, forwarded as DAP `__restart` metadata. Whether it is acted
+/// upon depends on the host IDE version.
+fn max_restarts(cfg_in: &Value, warnings: &mut Vec<String>) -> Result<u32> {
+    let max_restarts = match cfg_in.get("maxRestarts") {
+        None | Some(Value::Null) => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| ConfigError::new("maxRestarts", "not a non-negative integer").value(v))?,
+    };
+    if max_restarts > MAX_RESTARTS_WARN_THRESHOLD {
+        warnings.push(format!(
+            "maxRestarts = {} is unusually high (more than {}); a crashing adapter will be restarted repeatedly",
+            max_restarts, MAX_RESTARTS_WARN_THRESHOLD
+        ));
+    }
+    Ok(max_restarts)
+}
+
+/// `pathMappings` for lldb-dap, with variables expanded. Zed opens the local
+/// side, so inside WSL a Linux localRoot becomes its `\\wsl$\...` path.
+fn forwarded_path_mappings(mappings: &[PathMappingEntry], vars: &Variables, wsl_root: Option<&wsl::Root>) -> Vec<Value> {
+    mappings
+        .iter()
+        .map(|mapping| {
+            let mut local = normalize_path_trailing_slash(&expand_variables(&mapping.local_root, vars));
+            if let Some(root) = wsl_root
+                && local.starts_with('/')
+            {
+                local = root.to_windows(&local);
+            }
+            let remote = normalize_path_trailing_slash(&normalize_remote_path(&expand_variables(&mapping.remote_root, vars)));
+            serde_json::json!({ "localRoot": local, "remoteRoot": remote })
+        })
+        .collect()
+}
+
+/// lldb-dap's environment from `adapterEnv` (e.g., DEBUGINFOD_URLS). `env`
+/// does the same but reads as the debuggee's environment, so it is deprecated;
+/// adapterEnv wins where both set a variable.
+fn adapter_env(flags: &RemoteDebugConfig, warnings: &mut Vec<String>) -> Vec<(String, String)> {
+    if flags.env.is_some() {
+        warnings.push("`env` only sets lldb-dap's environment, not the program's; rename it to `adapterEnv`".to_string());
+    }
+    let mut adapter_env: BTreeMap<String, String> = BTreeMap::new();
+    for obj in flags.env.iter().chain(&flags.adapter_env) {
+        for (k, v) in obj {
+            let v = match v.as_str() {
+                Some(s) => s.to_string(),
+                None => v.to_string(),
+            };
+            adapter_env.insert(k.clone(), v);
+        }
+    }
+    adapter_env.into_iter().collect()
+}
+
+/// The first initCommands: the extension version, then the warnings. The
+/// version line lands in the debug console, so it comes along when a session
+/// is pasted in a bug report.
+fn header_commands(warnings: &[String]) -> Vec<String> {
+    std::iter::once(format!(
+        "script print(\"zed-lldb-remote extension v{}\")",
+        env!("CARGO_PKG_VERSION")
+    ))
+    .chain(warnings.iter().map(|w| warning_command(w)))
+    .collect()
+}
+
+/// Source the shared `lldbInitFile`; without `strictPaths`, LLDB reports a missing one
+fn init_file_command(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    vars: &Variables,
+    worktree_root: &str,
+    files: &impl host::FileCheck,
+) -> Result<Option<String>> {
+    match cfg_in.get("lldbInitFile") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(p)) if !p.is_empty() => {
+            let path = resolve_worktree_path(&expand_variables(p, vars), worktree_root);
+            if flags.strict_paths && !files.is_file(&path)? {
+                return Err(format!("`lldbInitFile` not found: {}", path));
+            }
+            Ok(Some(CommandBuilder::new("command source").arg(&path).build()))
+        }
+        Some(v) => Err(ConfigError::new("lldbInitFile", "not a path").value(v).into()),
+    }
+}
+
+/// The configured `program`, if any: a relative path is found under
+/// `programBaseDir`, and of several candidates the first that exists wins
+fn configured_program(
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+    worktree_root: &str,
+    vars: &Variables,
+    files: &impl host::FileCheck,
+) -> Result<Option<String>> {
+    // A relative `program` is found under `programBaseDir`, else left to
+    // lldb-dap, which runs in the worktree root unless `cwd` says otherwise
+    let base_dir = match cfg_in.get("programBaseDir") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(dir)) if !dir.is_empty() => Some(resolve_worktree_path(
+            &wsl::to_linux(&expand_variables(dir, vars)),
+            worktree_root,
+        )),
+        Some(v) => return Err(ConfigError::new("programBaseDir", "not a directory").value(v).into()),
+    };
+    // LLDB runs inside WSL, where the editor's `\\wsl$\...` paths don't exist
+    let program_path = |p: &str| {
+        let path = wsl::to_linux(&expand_variables(p, vars));
+        let path = match &base_dir {
+            Some(base) => resolve_worktree_path(&path, base),
+            None => path,
+        };
+        normalize_path_trailing_slash(&path)
+    };
+    match &flags.program {
+        None => Ok(None),
+        Some(OneOrMany::One(p)) => Ok(Some(program_path(p))),
+        // Candidates for fat binaries or per-arch builds: the first that exists
+        Some(OneOrMany::Many(candidates)) => {
+            // They are checked here, not by lldb-dap, so relative ones are
+            // taken from the worktree root when there's no `programBaseDir`
+            let paths: Vec<String> = candidates
+                .iter()
+                .map(|c| match base_dir {
+                    Some(_) => program_path(c),
+                    None => resolve_worktree_path(&program_path(c), worktree_root),
+                })
+                .collect();
+            if paths.is_empty() {
+                return Err(ConfigError::new("program", "is an empty array")
+                    .hint("expected one or more paths")
+                    .into());
+            }
+            let mut found = None;
+            for path in &paths {
+                if files.is_file(path)? {
+                    found = Some(path.clone());
+                    break;
+                }
+            }
+            found.map(Some).ok_or_else(|| {
+                format!("none of the `program` candidates exist: {}", paths.join(", "))
+            })
+        }
+    }
+}
+
+/// Expression language for mixed-language binaries
+fn source_language_command(cfg_in: &Value, warnings: &mut Vec<String>) -> Result<Option<String>> {
+    match cfg_in.get("sourceLanguage") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(lang)) if !lang.is_empty() => {
+            if !KNOWN_SOURCE_LANGUAGES.contains(&lang.as_str()) {
+                warnings.push(format!(
+                    "sourceLanguage {:?} is not one of {}; passing it to LLDB as is",
+                    lang,
+                    KNOWN_SOURCE_LANGUAGES.join(", ")
+                ));
+            }
+            Ok(Some(CommandBuilder::new("settings set target.language").word(lang).build()))
+        }
+        Some(v) => Err(ConfigError::new("sourceLanguage", "not a language name")
+            .value(v)
+            .hint("expected e.g. \"c++\" or \"rust\"")
+            .into()),
+    }
+}
+
+/// `exceptionBreakpoints`. The filters are lldb-dap's to define; only the
+/// shape is checked here.
+fn exception_filters(cfg_in: &Value) -> Result<Vec<Value>> {
+    match cfg_in.get("exceptionBreakpoints") {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(filters)) => {
+            for filter in filters {
+                if filter.get("filter").and_then(Value::as_str).is_none_or(str::is_empty) {
+                    return Err(ConfigError::new("exceptionBreakpoints", "has an entry that is not a filter")
+                        .value(filter)
+                        .hint("expected {\"filter\": \"cpp_throw\", ...}")
+                        .into());
+                }
+            }
+            Ok(filters.clone())
+        }
+        Some(v) => Err(ConfigError::new("exceptionBreakpoints", "not an array of filters")
+            .value(v)
+            .into()),
+    }
+}
+
+/// Raw fields for lldb-dap that the extension doesn't know, set last. Taking
+/// over a generated field has to be asked for, since it can break the session.
+fn apply_custom_request_args(
+    cfg_out: &mut serde_json::Map<String, Value>,
+    cfg_in: &Value,
+    flags: &RemoteDebugConfig,
+) -> Result<()> {
+    match cfg_in.get("customRequestArgs") {
+        None | Some(Value::Null) => Ok(()),
+        Some(Value::Object(custom)) => {
+            if !flags.custom_request_args_override {
+                let conflicts: Vec<&str> = custom
+                    .keys()
+                    .filter(|k| cfg_out.contains_key(*k))
+                    .map(String::as_str)
+                    .collect();
+                if !conflicts.is_empty() {
+                    return Err(ConfigError::new(
+                        "customRequestArgs",
+                        "sets fields the extension already generates",
+                    )
+                    .value(conflicts.join(", "))
+                    .hint("set `\"customRequestArgsOverride\": true` to replace them")
+                    .into());
+                }
+            }
+            cfg_out.extend(custom.iter().map(|(k, v)| (k.clone(), v.clone())));
+            Ok(())
+        }
+        Some(v) => Err(ConfigError::new("customRequestArgs", "not an object")
+            .value(v)
+            .hint("expected an object of lldb-dap configuration fields")
+            .into()),
+    }
+}
+
+/// Run the adapter from the worktree root unless told otherwise;
+/// an explicit `"cwd": null` leaves it to Zed
+fn adapter_cwd(cfg_in: &Value, vars: &Variables, worktree_root: &str) -> Result<Option<String>> {
+    match cfg_in.get("cwd") {
+        None => Ok((!worktree_root.is_empty()).then(|| worktree_root.to_string())),
+        Some(Value::Null) => Ok(None),
+        Some(Value::String(dir)) => Ok(Some(resolve_worktree_path(&expand_variables(dir, vars), worktree_root))),
+        Some(v) => Err(ConfigError::new("cwd", "not a path or null").value(v).into()),
+    }
+}
+
+impl Extension for Ext {
+    fn new() -> Self {
+        Self {
+            captured_configs: HashMap::new(),
+            cargo_metadata: HashMap::new(),
+            lldb_dap_versions: HashMap::new(),
+            deployed: HashMap::new(),
+            last_good_binaries: HashMap::new(),
+            dap_sessions: HashMap::new(),
+        }
+    }
+
+    // Capture the user's .zed/debug.json entry and decide attach/launch.
+    fn dap_request_kind(
+        &mut self,
+        adapter_name: String,
+        config: Value,
+    ) -> Result<StartDebuggingRequestArgumentsRequest> {
+        // Decide request kind
+        let req = match config
+            .get("request")
+            .and_then(|v| v.as_str())
+            .unwrap_or("attach")
+        {
+            "launch" => StartDebuggingRequestArgumentsRequest::Launch,
+            _ => StartDebuggingRequestArgumentsRequest::Attach,
+        };
+
+        // Save exact JSON to reuse later, next to other sessions' still to start
+        let fingerprint = Fingerprint::of(&config);
+        let captured = self.captured_configs.entry(adapter_name).or_default();
+        captured.retain(|c| c.fingerprint != fingerprint);
+        if captured.len() == MAX_CAPTURED_CONFIGS {
+            captured.remove(0);
+        }
+        captured.push(CapturedConfig {
+            json: config.to_string(),
+            fingerprint,
+            request_kind: req,
+        });
+        Ok(req)
+    }
+
+    // Offer an lldb-remote skeleton from the new session UI.
+    fn dap_config_to_scenario(&mut self, config: DebugConfig) -> Result<DebugScenario> {
+        scenario::from_debug_config(config)
+    }
+
+    // Let the gutter "Debug" action on cargo runnables start an lldb-remote session.
+    fn dap_locator_create_scenario(
+        &mut self,
+        locator_name: String,
+        build_task: TaskTemplate,
+        resolved_label: String,
+        debug_adapter_name: String,
+    ) -> Option<DebugScenario> {
+        if debug_adapter_name != ADAPTER_NAME {
+            return None;
+        }
+        scenario::from_cargo_task(locator_name, build_task, resolved_label, debug_adapter_name)
+    }
+
+    // After the locator's build step, find the program it produced.
+    fn run_dap_locator(
+        &mut self,
+        _locator_name: String,
+        build_task: TaskTemplate,
+    ) -> Result<DebugRequest> {
+        cargo::locate_program(build_task)
+    }
+
+    // Spawn lldb-dap and pass only what it needs.
+    fn get_dap_binary(
+        &mut self,
+        adapter_name: String,
+        config: zed::DebugTaskDefinition,
+        _user_provided_debug_adapter_path: Option<String>,
+        worktree: &Worktree,
+    ) -> Result<DebugAdapterBinary> {
+        let label = config.label.clone();
+        self.dap_binary(adapter_name, config, worktree).map_err(|e| {
+            // A dry run's report is meant to be read as JSON, so it stays as it is
+            if label.is_empty() || e.starts_with("DRY_RUN:") {
+                e
+            } else {
+                format!("{}\n  in debug configuration `{}`", e, label)
+            }
+        })
+    }
+}
+
+impl Ext {
+    /// The work of `get_dap_binary`, which adds the configuration's label to errors
+    fn dap_binary(
+        &mut self,
+        adapter_name: String,
+        config: zed::DebugTaskDefinition,
+        worktree: &Worktree,
+    ) -> Result<DebugAdapterBinary> {
+        // The captured JSON must belong to the session being started
+        let actual = match self.captured_configs.get(&adapter_name) {
+            Some(captured) if !captured.is_empty() && !config.config.is_empty() => {
+                match Fingerprint::of_json(&config.config) {
+                    Ok(actual) => Some(actual),
+                    Err(e) => return self.cached_binary(&config.label, e),
+                }
+            }
+            _ => None,
+        };
+        let captured = self.clear_pending_session(&adapter_name, actual)?;
+
+        let cfg_in = match parse_captured_config(&captured.json) {
+            Ok(cfg_in) => cfg_in,
+            Err(e) => return self.cached_binary(&config.label, e),
+        };
+        let resolved = extends::resolve(cfg_in, &config.label, worktree).and_then(|(cfg_in, inherit_warnings)| {
+            // Inherited fields come first, so overlays and this config override them.
+            // Per-OS settings win, and go through variable expansion like the rest.
+            let cfg_in = apply_platform_overlay(cfg_in)?;
+            let flags = RemoteDebugConfig::parse(&cfg_in)?;
+            Ok((cfg_in, inherit_warnings, flags))
+        });
+        let (cfg_in, inherit_warnings, flags) = match resolved {
+            Ok(resolved) => resolved,
+            Err(e) => return self.cached_binary(&config.label, e),
+        };
+
+        // Keep a known-good binary to fall back on if a later version of the
+        // config is broken. A dry run builds nothing to keep.
+        let label = config.label;
+        match self.build_binary(cfg_in, inherit_warnings, &flags, captured.request_kind, worktree) {
+            Ok(binary) => {
+                if flags.cache_config {
+                    self.last_good_binaries.insert(label, binary.clone());
+                } else {
+                    self.last_good_binaries.remove(&label);
+                }
+                Ok(binary)
+            }
+            Err(e) if flags.dry_run => Err(e),
+            Err(e) if flags.cache_config => self.cached_binary(&label, e),
+            // Without `cacheConfig` there is no falling back, now or later
+            Err(e) => {
+                self.last_good_binaries.remove(&label);
+                Err(e)
+            }
+        }
+    }
+
+    /// Build the adapter binary for a parsed config
+    fn build_binary(
+        &mut self,
+        cfg_in: Value,
+        inherit_warnings: Vec<String>,
+        flags: &RemoteDebugConfig,
+        request: StartDebuggingRequestArgumentsRequest,
+        worktree: &Worktree,
+    ) -> Result<DebugAdapterBinary> {
+        // Zed always reports a root, but nothing guarantees it; without one,
+        // relative paths stay relative.
+        let worktree_root = worktree.root_path().trim().to_string();
+        let vars = session_variables(&cfg_in, flags, &worktree_root, worktree.shell_env())?;
+
+        let (server_block, mut server) = match local_server(&cfg_in, flags, request, &vars, &worktree_root)? {
+            Some((block, server)) => (Some(block), Some(server)),
+            None => (None, None),
+        };
+        let named_pipe = named_pipe(&cfg_in)?;
+        let address = match port_file_address(&cfg_in, server.is_some(), worktree, &worktree_root, &vars)? {
+            Some(address) => Some(address),
+            None => server.as_ref().and_then(server::Server::address),
+        };
+        let server_port = server.as_ref().and_then(server::Server::port);
+        let mut targets = session_targets(flags, request, address, server_port, named_pipe.is_some(), &vars)?;
+        let secondary_targets = targets.split_off(targets.len().min(1));
+        let target = targets.pop();
+
+        // Build everything but start nothing, for checking what a config turns into
+        let dry_run = flags.dry_run;
+
+        // Problems worth telling the user about, but not worth failing the session for
+        let mut warnings: Vec<String> = inherit_warnings;
+        warnings.extend(unknown_fields_warning(&cfg_in));
+        if worktree_root.is_empty() {
+            warnings.push(
+                "Zed reported no worktree root: relative paths are used as written and `cwd` is left to Zed".to_string(),
+            );
+        }
+        // What the extension decided, printed with `verbose`
+        let mut notes = vec![format!("home directory: {}", vars.home)];
+        match (&target, &named_pipe) {
+            (Some(target), _) => notes.extend(
+                std::iter::once(target)
+                    .chain(&secondary_targets)
+                    .map(|t| format!("connecting to {}{}", t.addr, if t.tls { " with TLS" } else { "" })),
+            ),
+            (None, Some(pipe)) => notes.push(format!("connecting to named pipe {}", pipe)),
+            (None, None) => notes.push("no target: lldb-dap launches the program locally".to_string()),
+        }
+        if let Some(block) = server_block {
+            notes.push(format!("starting a local debug server from `{}`", block));
+        }
+
+        let command = resolve_adapter_command(&cfg_in, worktree, &vars, &worktree_root, &mut warnings)?;
+        notes.push(format!("adapter: {}", command));
+        let elevation = elevate::prefix(&cfg_in, worktree, &vars, server.is_some())?;
+
+        let session_id = session_id(&cfg_in)?;
+        let running_session = self.running_session(flags, session_id.as_deref(), &mut warnings)?;
+
+        // Spawning the adapter just to ask its version is slow, so it is opt-in.
+        // The result is cached on `self` for later sessions.
+        if flags.check_lldb_dap_version {
+            match self.lldb_dap_major_version(&command, worktree) {
+                Some(v) if v < MIN_LLDB_DAP_VERSION => warnings.push(format!(
+                    "{} reports LLVM {}; versions older than {} may not support every option",
+                    command, v, MIN_LLDB_DAP_VERSION
+                )),
+                Some(_) => {}
+                None => warnings.push(format!("could not determine the version of {}", command)),
+            }
+        }
+
+        let tls_cmds = match &target {
+            Some(target) if target.tls => tls_commands(&cfg_in, &vars, &mut warnings),
+            _ => Vec::new(),
+        };
+
+        // Build attach commands
+        let mut attach_cmds = Vec::new();
+
+        let kernel = session_kernel(&cfg_in, &vars, &worktree_root)?;
+        let triple = vars.target_triple.as_deref();
+        let (program, mut program_args) =
+            self.resolve_program(&cfg_in, flags, kernel.as_ref(), worktree, &worktree_root, &vars)?;
+        notes.push(match &program {
+            Some(program) => format!("program: {}", program),
+            None => "no `program`: skipping `target create`, symbols come from the stub".to_string(),
+        });
+        let placeholders = Placeholders {
+            target: target.as_ref(),
+            program: program.as_deref(),
+        };
+        if program.is_none() {
+            warnings.extend(unfilled_program_warning(flags));
+        }
+
+        if let Some(server) = &mut server
+            && let Some(block) = server.needs_program()
+        {
+            let program = program
+                .as_deref()
+                .ok_or_else(|| format!("`{}` needs a `program` to run", block))?;
+            server.set_program(program);
+        }
+
+        // Copy the fresh binary to the board and restart its debug server
+        if let Some(deploy) = cfg_in.get("deploy")
+            && !dry_run
+        {
+            deploy::run(deploy, program.as_deref(), &vars, worktree, &mut self.deployed)?;
+        }
+
+        // The user's own setup on the host, e.g. starting the stub over ssh. The
+        // post-session hook is checked now but runs when the adapter has exited.
+        let post_session = hooks::parse(&cfg_in, "postSessionCommand", &vars)?;
+        if let Some(hook) = hooks::parse(&cfg_in, "preConnectCommand", &vars)?
+            && !dry_run
+        {
+            hook.run(worktree.shell_env())?;
+        }
+
+        let addrs: Vec<&str> = target
+            .iter()
+            .chain(&secondary_targets)
+            .map(|t| t.addr.as_str())
+            .collect();
+        check_stub(&cfg_in, flags, &addrs, named_pipe.as_deref(), server.is_some(), &vars, &mut warnings)?;
+
+        // The platform comes first, so the target is created for it
+        if let Some(platform) = server.as_ref().and_then(server::Server::platform) {
+            attach_cmds.push(format!("platform select {}", platform));
+        }
+
+        // In extended-remote mode (`gdbserver --multi`, qemu -S) the stub starts
+        // `remoteProgram` itself after LLDB has connected
+        let remote_program = if flags.extended_remote {
+            if request == StartDebuggingRequestArgumentsRequest::Launch {
+                return Err("`extendedRemote` needs \"request\": \"attach\"; the stub launches the program".to_string());
+            }
+            if target.is_none() && named_pipe.is_none() {
+                return Err("`extendedRemote` needs a `target` to connect to".to_string());
+            }
+            if !secondary_targets.is_empty() || kernel.is_some() {
+                return Err("`extendedRemote` cannot be combined with several targets or `kernel`".to_string());
+            }
+            if program.is_none() {
+                return Err("`extendedRemote` needs a local `program` for the target's symbols".to_string());
+            }
+            match cfg_in.get("remoteProgram") {
+                Some(Value::String(p)) if !p.is_empty() => Some(expand_variables(p, &vars)),
+                Some(v) => {
+                    return Err(ConfigError::new("remoteProgram", "not a path on the target")
+                        .value(v)
+                        .into());
+                }
+                None => return Err("`extendedRemote` needs `remoteProgram`, the path for the stub to run".to_string()),
+            }
+        } else {
+            None
+        };
+
+        // lldb-dap restarts only launch sessions, so a restartable attach goes to it
+        // as a launch whose launchCommands are the attach sequence. lldb-dap then
+        // creates the target from `program` once and keeps it, with its breakpoints.
+        let restartable = match cfg_in.get("restartable") {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+            Some(Value::Bool(true)) if request == StartDebuggingRequestArgumentsRequest::Launch => {
+                warnings.push("`restartable` only applies to attach requests; launch sessions restart as they are".to_string());
+                false
+            }
+            Some(Value::Bool(true)) => {
+                if server.is_some() {
+                    return Err("`restartable` cannot be combined with a server the session starts".to_string());
+                }
+                if remote_program.is_some() || !secondary_targets.is_empty() {
+                    return Err("`restartable` cannot be combined with `extendedRemote` or several targets".to_string());
+                }
+                true
+            }
+            Some(v) => return Err(ConfigError::new("restartable", "not a boolean").value(v).into()),
+        };
+
+        let architecture = architecture(&cfg_in)?;
+        if architecture.is_some() {
+            if program.is_none() {
+                warnings.push("`architecture` has no effect without a `program`".to_string());
+            } else if restartable
+                || (request == StartDebuggingRequestArgumentsRequest::Launch && flags.launch_commands.is_none())
+            {
+                // lldb-dap creates the target from `program` itself
+                warnings.push("`architecture` is only applied to `attach` and `launchCommands` sessions".to_string());
+            }
+        }
+
+        let mut target_cmds = Vec::new();
+        if let Some(program) = &program
+            && !restartable
+        {
+            let cmd = match &remote_program {
+                Some(remote) => target_create(architecture.as_deref()).word("--remote-file").arg(remote),
+                None => target_create(architecture.as_deref()),
+            };
+            target_cmds.push(cmd.arg(program).build());
+        }
+        target_cmds.extend(load_address_commands(&cfg_in, program.as_deref())?);
+        if let Some(kernel) = &kernel {
+            target_cmds.extend(kernel.pre_connect_commands());
+        }
+
+        let attach_timeout = attach_timeout(&cfg_in)?;
+        let first_stop_timeout = first_stop_timeout(&cfg_in)?;
+        let connect_attempts = connect_attempts(&cfg_in)?;
+        let connect = target
+            .as_ref()
+            .map(|t| {
+                // A decoded host may hold a space
+                if t.addr.contains(char::is_whitespace) {
+                    CommandBuilder::new("gdb-remote").arg(&t.addr).build()
+                } else {
+                    CommandBuilder::new("gdb-remote").word(&t.addr).build()
+                }
+            })
+            .or_else(|| {
+                named_pipe.as_ref().map(|pipe| {
+                    CommandBuilder::new("gdb-remote")
+                        .arg(&format!("file://{}", pipe))
+                        .build()
+                })
+            });
+
+        let fork_cmds = fork_commands(&cfg_in, &mut warnings)?;
+
+        // Then connect via gdb-remote
+        let mut connect_cmds = Vec::new();
+        if let Some(connect) = connect {
+            let connect = if connect_attempts > 1 {
+                let address = target
+                    .as_ref()
+                    .map(|t| t.addr.clone())
+                    .or_else(|| named_pipe.clone())
+                    .unwrap_or_default();
+                retry_commands(&connect, &address, connect_attempts)
+            } else {
+                vec![connect]
+            };
+            match first_stop_timeout {
+                Some(secs) => {
+                    connect_cmds.push(format!(
+                        "settings set plugin.process.gdb-remote.packet-timeout {}",
+                        secs
+                    ));
+                    connect_cmds.extend(connect);
+                    // Back to `attachTimeout`, or LLDB's default
+                    connect_cmds.push(match attach_timeout {
+                        Some(secs) => format!(
+                            "settings set plugin.process.gdb-remote.packet-timeout {}",
+                            secs
+                        ),
+                        None => "settings clear plugin.process.gdb-remote.packet-timeout".to_string(),
+                    });
+                }
+                None => connect_cmds.extend(connect),
+            }
+        }
+
+        if target.is_some() || named_pipe.is_some() {
+            connect_cmds.extend(post_connect_commands(&cfg_in, flags, kernel.as_ref())?);
+        }
+
+        attach_cmds.extend(connection_sequence(target_cmds, connect_cmds, remote_program.is_some()));
+
+        // Watch- and breakpoints go in before the user's commands get a chance to resume the process
+        attach_cmds.extend(watchpoint_commands(&cfg_in)?);
+        attach_cmds.extend(breakpoint_commands(&cfg_in, &vars, &worktree_root)?);
+
+        // Each further core gets its own target; the first stays selected
+        if !secondary_targets.is_empty() {
+            let program = program.as_ref().ok_or_else(|| {
+                "several `target` addresses need a `program` to create a target for each".to_string()
+            })?;
+            for target in &secondary_targets {
+                attach_cmds.push(target_create(architecture.as_deref()).arg(program).build());
+                attach_cmds.push(CommandBuilder::new("gdb-remote").word(&target.addr).build());
+            }
+            attach_cmds.push("target select 0".to_string());
+        }
+
+        // The program's own arguments come after any the test harness needs
+        let user_args_key = match (cfg_in.get("programArgs"), cfg_in.get("args")) {
+            (Some(_), Some(_)) => {
+                return Err("`programArgs` and `args` cannot both be set (`args` is an alias)".to_string());
+            }
+            (Some(_), None) => Some("programArgs"),
+            (None, Some(_)) => Some("args"),
+            (None, None) => None,
+        };
+        if let Some(key) = user_args_key {
+            if request == StartDebuggingRequestArgumentsRequest::Launch || remote_program.is_some() {
+                program_args.extend(program_arguments(&cfg_in[key], key, &vars)?);
+            } else {
+                warnings.push(format!("`{}` only applies to launch requests and `extendedRemote`, and is ignored", key));
+            }
+        }
+
+        // The stub runs the program once breakpoints are in
+        let stop_on_entry = flags.stop_on_entry;
+        let break_main = flags.stop_on_entry_break_main;
+        if remote_program.is_some() {
+            attach_cmds.extend(remote_launch_commands(stop_on_entry, break_main, &program_args));
+        }
+
+        // Then append user's attachCommands, from the file first
+        let attach_file_cmds =
+            command_file(&cfg_in, "attachCommandsFile", worktree, &worktree_root, &vars)?;
+        attach_cmds.extend(
+            attach_file_cmds
+                .iter()
+                .map(|c| prepare_command(c, &vars, &placeholders)),
+        );
+        attach_cmds.extend(user_commands(flags.commands("attachCommands"), &vars, &placeholders));
+
+        let attached = remote_program.is_none() && (target.is_some() || named_pipe.is_some());
+        attach_cmds.extend(after_attach_commands(&cfg_in, flags, attached)?);
+
+        // Build outgoing configuration
+        let mut cfg_out = serde_json::json!({
+            "request": "attach",
+            "attachCommands": attach_cmds
+        });
+
+        // A launch needs lldb-dap to know what to run
+        if request == StartDebuggingRequestArgumentsRequest::Launch {
+            let obj = cfg_out.as_object_mut().unwrap();
+            obj.insert("request".into(), serde_json::json!("launch"));
+            if let Some(program) = &program {
+                obj.insert("program".into(), serde_json::json!(program));
+            }
+            if !program_args.is_empty() {
+                obj.insert("args".into(), serde_json::json!(program_args));
+            }
+
+            // launchCommands replace lldb-dap's own launch, so create the target first.
+            // lldb-dap would create one from `program` too, so it isn't told the program.
+            if flags.launch_commands.is_some() {
+                obj.remove("program");
+                let user_cmds = user_commands(flags.commands("launchCommands"), &vars, &placeholders);
+                let create = program.as_ref().map(|p| target_create(architecture.as_deref()).arg(p).build());
+                obj.insert("launchCommands".into(), serde_json::json!(launch_commands(create, user_cmds)));
+            }
+        } else if flags.launch_commands.is_some() {
+            return Err(
+                "`launchCommands` is only valid with \"request\": \"launch\" (use `attachCommands` when attaching)"
+                    .to_string(),
+            );
+        }
+        if let Some((key, value)) = terminal_field(flags, request, &mut warnings) {
+            cfg_out.as_object_mut().unwrap().insert(key.into(), value);
+        }
+
+        if restartable {
+            let mut launch_cmds = rebuild_commands(&cfg_in, &worktree_root, triple, program.as_deref(), &vars)?;
+            let obj = cfg_out.as_object_mut().unwrap();
+            if let Some(Value::Array(cmds)) = obj.remove("attachCommands") {
+                launch_cmds.extend(cmds.into_iter().filter_map(|c| c.as_str().map(str::to_string)));
+            }
+            obj.insert("request".into(), serde_json::json!("launch"));
+            if let Some(program) = &program {
+                obj.insert("program".into(), serde_json::json!(program));
+            }
+            obj.insert("launchCommands".into(), serde_json::json!(launch_cmds));
+        }
+
+        // Preserve stopOnEntry if present
+        if let Some(soe) = cfg_in.get("stopOnEntry") {
+            cfg_out
+                .as_object_mut()
+                .unwrap()
+                .insert("stopOnEntry".into(), soe.clone());
+        }
+
+        let max_restarts = max_restarts(&cfg_in, &mut warnings)?;
+        if max_restarts > 0 {
+            cfg_out.as_object_mut().unwrap().insert(
+                "__restart".into(),
+                serde_json::json!({ "maxRestarts": max_restarts }),
+            );
+        }
+
+        // Only an attach waits on the stub
+        if let Some(secs) = attach_timeout {
+            if request == StartDebuggingRequestArgumentsRequest::Launch {
+                warnings.push("`attachTimeout` only applies to attach requests; ignoring it".to_string());
+            } else {
+                cfg_out
+                    .as_object_mut()
+                    .unwrap()
+                    .insert("timeout".into(), serde_json::json!(secs));
+            }
+        }
+
+        // For attach, DO NOT forward program - we handle it in attachCommands instead
+        // This prevents lldb-dap from loading symbols before gdb-remote connects
+
+        if let Some(mappings) = &flags.path_mappings {
+            if flags.validate_path_mappings {
+                validate_path_mappings(mappings, &vars, &worktree_root)?;
+            }
+            let mappings = forwarded_path_mappings(mappings, &vars, wsl::detect(flags).as_ref());
+            cfg_out
+                .as_object_mut()
+                .unwrap()
+                .insert("pathMappings".into(), serde_json::json!(mappings));
+        }
+
+        let envs = adapter_env(flags, &mut warnings);
+        let language_cmd = source_language_command(&cfg_in, &mut warnings)?;
+
+        let lldbinit = resolve_lldbinit(&cfg_in, worktree, &worktree_root, &vars)?;
+
+        // Build initCommands: the extension version and warnings first, then user's,
+        // then auto-generated source-map from pathMappings
+        let mut init_cmds = header_commands(&warnings);
+        let notes_at = init_cmds.len();
+
+        // The named profile's init file comes before everything the user configured
+        if let Some(path) = resolve_profile(&cfg_in, worktree, &worktree_root, &vars, &mut notes)? {
+            init_cmds.push(CommandBuilder::new("command source").arg(&path).build());
+        }
+
+        init_cmds.extend(init_file_command(&cfg_in, flags, &vars, &worktree_root, &host::TestFileCheck)?);
+
+        // Source the project .lldbinit before anything the user wrote
+        if let Some(path) = lldbinit {
+            init_cmds.push(
+                CommandBuilder::new("command source --silent-run true")
+                    .arg(&path)
+                    .build(),
+            );
+        }
+
+        // Transport settings must be in place before the user's commands
+        init_cmds.extend(tls_cmds);
+        init_cmds.extend(fork_cmds);
+        if let Some(secs) = attach_timeout
+            && request != StartDebuggingRequestArgumentsRequest::Launch
+        {
+            init_cmds.push(format!(
+                "settings set plugin.process.gdb-remote.packet-timeout {}",
+                secs
+            ));
+        }
+
+        // Peripheral definitions are loaded before connecting, so they are there at the first stop
+        init_cmds.extend(svd::commands(&cfg_in, &vars, &worktree_root)?);
+        if let Some(kernel) = &kernel {
+            init_cmds.extend(kernel.init_commands());
+        }
+
+        init_cmds.extend(language_cmd);
+
+        // First, add user's initCommands if provided, from the file first
+        let init_file_cmds =
+            command_file(&cfg_in, "initCommandsFile", worktree, &worktree_root, &vars)?;
+        init_cmds.extend(
+            init_file_cmds
+                .iter()
+                .map(|c| prepare_command(c, &vars, &placeholders)),
+        );
+        init_cmds.extend(user_commands(flags.commands("initCommands"), &vars, &placeholders));
+
+        // Then auto-generate source-map settings from pathMappings
+        let source_map = source_map_commands(flags.path_mappings.as_deref(), &vars, flags.reverse_source_map, &init_cmds, &mut notes);
+        init_cmds.extend(source_map);
+
+        // Build-machine prefixes to drop, leaving paths relative to the sources
+        init_cmds.extend(strip_prefix_commands(&cfg_in, &vars, &worktree_root)?);
+
+        cfg_out
+            .as_object_mut()
+            .unwrap()
+            .extend(run_command_fields(flags, &vars, &placeholders));
+
+        let filters = exception_filters(&cfg_in)?;
+        if !filters.is_empty()
+            && let Some(obj) = cfg_out.as_object_mut()
+        {
+            obj.insert("exceptionBreakpointFilters".into(), Value::Array(filters));
+        }
+
+        // Right after the warnings, where they are easy to find in the debug console
+        if flags.verbose {
+            init_cmds.splice(notes_at..notes_at, notes.iter().map(|n| note_command(n)));
+        }
+
+        // The same setting from a command file, inline and generated is only noise
+        if flags.dedup_init_commands {
+            dedup_commands(&mut init_cmds);
+        }
+
+        // What happens to the debuggee when the session ends: forwarded as
+        // `terminateDebuggee`, and as exitCommands for the command-driven path
+        let detach = detach_on_exit(&cfg_in, request)?;
+        let command_driven = cfg_out.get("request") == Some(&serde_json::json!("attach"))
+            || cfg_out.get("launchCommands").is_some();
+        let exit_cmds = exit_commands(flags, detach, command_driven, &vars, &placeholders);
+        if let Some(obj) = cfg_out.as_object_mut() {
+            if let Some(detach) = detach {
+                obj.insert("terminateDebuggee".into(), Value::Bool(!detach));
+            }
+            if !exit_cmds.is_empty() {
+                obj.insert("exitCommands".into(), serde_json::json!(exit_cmds));
+            }
+        }
+
+        // Expansions happen all the way through, so their warnings are only known now
+        let shell_warnings = vars.shell_warnings.take();
+        init_cmds.splice(notes_at..notes_at, shell_warnings.iter().map(|w| warning_command(w)));
+
+        // Add initCommands to config if we have any
+        if !init_cmds.is_empty()
+            && let Some(obj) = cfg_out.as_object_mut()
+        {
+            obj.insert("initCommands".into(), serde_json::json!(init_cmds));
+        }
+
+        apply_custom_request_args(cfg_out.as_object_mut().unwrap(), &cfg_in, flags)?;
+
+        let cwd = adapter_cwd(&cfg_in, &vars, &worktree_root)?;
+
+        // Whitespace doesn't matter to lldb-dap, only to people reading the logs
+        let configuration = if flags.human_readable_config {
+            serde_json::to_string_pretty(&cfg_out)
+                .map_err(|e| format!("failed to serialize the lldb-dap configuration: {}", e))?
+        } else {
+            cfg_out.to_string()
+        };
+
+        let request_args = StartDebuggingRequestArguments {
+            configuration,
+            request: if restartable {
+                StartDebuggingRequestArgumentsRequest::Launch
+            } else {
+                request
+            },
+        };
+
+        // The adapter is already running, so Zed only connects to it
+        if let Some(connection) = running_session {
+            let binary = DebugAdapterBinary {
+                command: None,
+                arguments: vec![],
+                cwd,
+                envs,
+                request_args,
+                connection: Some(connection),
+            };
+            if dry_run {
+                return Err(dry_run_report(&binary));
+            }
+            return Ok(binary);
+        }
+
+        let (adapter_args, connection) = self.adapter_listen(session_id.as_deref(), dry_run)?;
+
+        // With a server, a shell wrapper starts it and then runs the adapter.
+        // `elevate` applies to the server if there is one, else to the adapter.
+        let (command, arguments) = match (&mut server, &elevation) {
+            (Some(server), elevation) => {
+                if let Some(prefix) = elevation {
+                    server.elevate(prefix);
+                }
+                ("sh".to_string(), server.wrap(&command, &adapter_args))
+            }
+            (None, Some(prefix)) => elevate::wrap_adapter(prefix, command, adapter_args, &envs),
+            (None, None) => (command, adapter_args),
+        };
+        let (command, arguments) = match &post_session {
+            Some(hook) => hook.wrap(command, arguments),
+            None => (command, arguments),
+        };
+
+        let binary = DebugAdapterBinary {
+            command: Some(command),
+            arguments,
+            cwd,
+            envs,
+            request_args,
+            connection,
+        };
+        if dry_run {
+            return Err(dry_run_report(&binary));
+        }
+        Ok(binary)
+    }
+
+    /// The `program` to debug and the arguments its build adds, e.g. a test
+    /// binary's filter. It comes from a cargo build or test build if
+    /// configured, else from `program`, else from the workspace's only binary
+    /// target.
+    fn resolve_program(
+        &mut self,
+        cfg_in: &Value,
+        flags: &RemoteDebugConfig,
+        kernel: Option<&kernel::Kernel>,
+        worktree: &Worktree,
+        worktree_root: &str,
+        vars: &Variables,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        let sources: Vec<&str> = ["program", "cargo", "cargoTest", "kernel"]
+            .into_iter()
+            .filter(|key| cfg_in.get(*key).is_some())
+            .collect();
+        if sources.len() > 1 {
+            return Err(format!("only one of {} can be set", sources.join(", ")));
+        }
+        let triple = vars.target_triple.as_deref();
+        if let Some(kernel) = kernel {
+            return Ok((Some(kernel.vmlinux.clone()), Vec::new()));
+        }
+        if let Some(cargo) = cfg_in.get("cargo") {
+            return Ok((Some(cargo::build_program(cargo, worktree, triple)?), Vec::new()));
+        }
+        if let Some(cargo_test) = cfg_in.get("cargoTest") {
+            let test = cargo::build_test(cargo_test, worktree, triple)?;
+            return Ok((Some(test.program), test.args));
+        }
+
+        let program = match configured_program(cfg_in, flags, worktree_root, vars, &host::TestFileCheck)? {
+            Some(program) => Some(program),
+            // A replay debugs whatever was recorded
+            None => match cfg_in.get("rr") {
+                Some(rr) => Some(server::rr_program(rr, vars)?),
+                None => self.cargo_default_program(cfg_in, worktree, vars)?,
+            },
+        };
+        Ok((program, Vec::new()))
+    }
+
+    /// The running lldb-dap to reconnect to with `reAttach`, if the session
+    /// named `session_id` started one
+    fn running_session(
+        &self,
+        flags: &RemoteDebugConfig,
+        session_id: Option<&str>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Option<zed::TcpArguments>> {
+        match (session_id, flags.re_attach) {
+            (None, true) => Err("`reAttach` needs the `sessionId` of the session to reconnect to".to_string()),
+            (Some(id), true) => {
+                let running = self.dap_sessions.get(id).copied();
+                if running.is_none() {
+                    warnings.push(format!(
+                        "no lldb-dap was started for session {:?}; starting a new one",
+                        id
+                    ));
+                }
+                Ok(running)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// lldb-dap's arguments and Zed's connection to it. For a named session
+    /// lldb-dap listens on a port Zed picks, and Zed connects to it; the port
+    /// is kept for `reAttach`.
+    fn adapter_listen(
+        &mut self,
+        session_id: Option<&str>,
+        dry_run: bool,
+    ) -> Result<(Vec<String>, Option<zed::TcpArguments>)> {
+        let Some(id) = session_id else {
+            return Ok((vec![], None));
+        };
+        let connection = zed::resolve_tcp_template(zed::TcpArgumentsTemplate {
+            host: None,
+            port: None,
+            timeout: None,
+        })?;
+        if !dry_run {
+            self.dap_sessions.insert(id.to_string(), connection);
+        }
+        let host = std::net::Ipv4Addr::from(connection.host);
+        Ok((
+            vec![
+                "--connection".to_string(),
+                format!("listen://{}:{}", host, connection.port),
+            ],
+            Some(connection),
+        ))
+    }
+}
+
+zed::register_extension!(Ext);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars() -> Variables {
+        Variables {
+            home: "/home/me".to_string(),
+            target_triple: None,
+            allow_shell_commands: false,
+            shell_env: Vec::new(),
+            shell_timeout_secs: DEFAULT_SHELL_EXPANSION_TIMEOUT_SECS,
+            shell_warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The `args` patterns extension.toml declares for `command`.
+    fn declared_args(command: &str) -> Vec<Vec<String>> {
+        include_str!("../extension.toml")
+            .split("[[capabilities]]")
+            .filter(|block| block.contains(&format!("command = \"{}\"", command)))
+            .filter_map(|block| block.lines().find_map(|line| line.strip_prefix("args = ")))
+            .map(|args| serde_json::from_str(args).unwrap())
+            .collect()
+    }
+
+    /// Zed's matching: `*` takes one argument, a trailing `**` the rest.
+    fn capability_allows(pattern: &[String], args: &[&str]) -> bool {
+        match (pattern.split_first(), args.split_first()) {
+            (Some((p, _)), _) if p == "**" => true,
+            (Some((p, pattern)), Some((a, args))) => {
+                (p == "*" || p == a) && capability_allows(pattern, args)
+            }
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn a_truncated_configuration_reports_where_it_stopped() {
+        let e = parse_captured_config("{\n  \"program\": \"/bin/app\",\n").unwrap_err();
+        assert!(e.starts_with("invalid debug configuration JSON: EOF while parsing a value"), "{}", e);
+        assert!(e.ends_with("at line 2 column 24"), "{}", e);
+        assert_eq!(parse_captured_config(" \n").unwrap(), json!({}));
+    }
+
+    #[test]
+    fn shell_expansion_argv_matches_a_declared_capability() {
+        let args = shell_expansion_args("git rev-parse HEAD", "5");
+        assert!(declared_args("sh").iter().any(|pattern| capability_allows(pattern, &args)));
+        assert!(!declared_args("sh").iter().any(|pattern| capability_allows(pattern, &args[..4])));
+    }
+
+    fn mappings(cfg_in: &Value) -> Option<Vec<PathMappingEntry>> {
+        RemoteDebugConfig::parse(cfg_in).unwrap().path_mappings
+    }
+
+    #[test]
+    fn source_map_is_appended_longest_prefix_first() {
+        let cfg_in = json!({"pathMappings": [
+            {"remoteRoot": "/build", "localRoot": "/src"},
+            {"remoteRoot": "/build/external/", "localRoot": "${HOME}/ext"},
+            {"remoteRoot": "/same", "localRoot": "/same"},
+        ]});
+        let mut notes = Vec::new();
+        assert_eq!(
+            source_map_commands(mappings(&cfg_in).as_deref(), &vars(), false, &[], &mut notes),
+            [
+                r#"settings append target.source-map "/build/external" "/home/me/ext""#,
+                r#"settings append target.source-map "/build" "/src""#,
+            ]
+        );
+        assert_eq!(notes, ["2 of 3 pathMappings need a source-map entry"]);
+    }
+
+    #[test]
+    fn reverse_source_map_appends_reversed_entries_after_the_others() {
+        let cfg_in = json!({"pathMappings": [
+            {"remoteRoot": "/b", "localRoot": "/local/b"},
+            {"remoteRoot": "/a/long", "localRoot": "/x"},
+        ]});
+        assert_eq!(
+            source_map_commands(mappings(&cfg_in).as_deref(), &vars(), true, &[], &mut Vec::new()),
+            [
+                r#"settings append target.source-map "/a/long" "/x""#,
+                r#"settings append target.source-map "/b" "/local/b""#,
+                r#"settings append target.source-map "/local/b" "/b""#,
+                r#"settings append target.source-map "/x" "/a/long""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn a_reversed_mapping_swaps_its_own_arguments() {
+        let cfg_in = json!({"pathMappings": [
+            {"remoteRoot": "/build", "localRoot": "/src", "reverseSourceMap": true},
+            {"remoteRoot": "/opt", "localRoot": "/o", "reverseSourceMap": false},
+        ]});
+        assert_eq!(
+            source_map_commands(mappings(&cfg_in).as_deref(), &vars(), false, &[], &mut Vec::new()),
+            [
+                r#"settings append target.source-map "/opt" "/o""#,
+                r#"settings append target.source-map "/src" "/build""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn source_map_skips_mappings_the_user_wrote_however_quoted() {
+        let cfg_in = json!({"pathMappings": [
+            {"remoteRoot": "/build", "localRoot": "/home/me/My Src"},
+            {"remoteRoot": "/opt", "localRoot": "/o"},
+        ]});
+        let init_cmds = [
+            "settings set target.source-map /build/ '/home/me/My Src'".to_string(),
+            r#"settings   append target.source-map "/o" "/opt""#.to_string(),
+        ];
+        assert_eq!(
+            source_map_commands(mappings(&cfg_in).as_deref(), &vars(), true, &init_cmds, &mut Vec::new()),
+            [
+                r#"settings append target.source-map "/opt" "/o""#,
+                r#"settings append target.source-map "/home/me/My Src" "/build""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn dedup_keeps_the_first_of_differently_quoted_duplicates() {
+        let mut cmds = vec![
+            "settings set target.language rust".to_string(),
+            r#"settings set target.source-map "/build" "/src""#.to_string(),
+            "breakpoint set -n main".to_string(),
+            "settings set target.source-map /build /src".to_string(),
+            "settings  set target.language 'rust'".to_string(),
+            "breakpoint set -n main".to_string(),
+        ];
+        dedup_commands(&mut cmds);
+        assert_eq!(
+            cmds,
+            [
+                "settings set target.language rust",
+                r#"settings set target.source-map "/build" "/src""#,
+                "breakpoint set -n main",
+            ]
+        );
+    }
+
+    #[test]
+    fn cached_binary_is_reused_for_its_label_only() {
+        let mut ext = <Ext as zed::Extension>::new();
+        let binary = DebugAdapterBinary {
+            command: Some("lldb-dap-20".to_string()),
+            arguments: Vec::new(),
+            envs: Vec::new(),
+            cwd: None,
+            connection: None,
+            request_args: StartDebuggingRequestArguments {
+                configuration: json!({"initCommands": ["settings set target.language rust"]}).to_string(),
+                request: StartDebuggingRequestArgumentsRequest::Attach,
+            },
+        };
+        ext.last_good_binaries.insert("board".to_string(), binary);
+
+        let cached = ext.cached_binary("board", "`target`: is missing".to_string()).unwrap();
+        let cfg: Value = serde_json::from_str(&cached.request_args.configuration).unwrap();
+        assert_eq!(
+            cfg["initCommands"],
+            json!([
+                warning_command("`target`: is missing; reusing the last configuration that worked"),
+                "settings set target.language rust",
+            ])
+        );
+        assert_eq!(
+            ext.cached_binary("other", "`target`: is missing".to_string()).unwrap_err(),
+            "`target`: is missing"
+        );
+    }
+
+    #[test]
+    fn attaching_detaches_on_exit_unless_told_otherwise() {
+        use StartDebuggingRequestArgumentsRequest::{Attach, Launch};
+        assert_eq!(detach_on_exit(&json!({}), Attach).unwrap(), Some(true));
+        assert_eq!(detach_on_exit(&json!({}), Launch).unwrap(), None);
+        assert_eq!(detach_on_exit(&json!({"terminateDebuggee": true}), Attach).unwrap(), Some(false));
+        assert_eq!(detach_on_exit(&json!({"detachOnExit": true}), Launch).unwrap(), Some(true));
+        assert!(detach_on_exit(&json!({"detachOnExit": true, "terminateDebuggee": true}), Attach).is_err());
+        assert!(detach_on_exit(&json!({"detachOnExit": "yes"}), Attach).is_err());
+    }
+
+    #[test]
+    fn launch_commands_create_the_target_once() {
+        let create = Some(r#"target create "/bin/app""#.to_string());
+        assert_eq!(
+            launch_commands(create.clone(), vec!["process launch --stop-at-entry".to_string()]),
+            [r#"target create "/bin/app""#, "process launch --stop-at-entry"]
+        );
+        assert_eq!(
+            launch_commands(create, vec!["target  create /other".to_string(), "process launch".to_string()]),
+            ["target  create /other", "process launch"]
+        );
+        assert_eq!(launch_commands(None, vec!["process launch".to_string()]), ["process launch"]);
+    }
+
+    #[test]
+    fn generated_paths_with_spaces_stay_one_argument() {
+        let cfg_in = json!({
+            "pathMappings": [{"remoteRoot": "/build/My Project", "localRoot": "${HOME}/My \"Project\"/src"}],
+            "stripPathPrefix": "/ci runner/build",
+        });
+        let cmds = source_map_commands(mappings(&cfg_in).as_deref(), &vars(), false, &[], &mut Vec::new())
+            .into_iter()
+            .chain(strip_prefix_commands(&cfg_in, &vars(), "/work/my proj").unwrap());
+        let words: Vec<Vec<String>> = cmds.map(|c| command::split(&c)).collect();
+        assert_eq!(
+            words,
+            [
+                ["settings", "append", "target.source-map", "/build/My Project", "/home/me/My \"Project\"/src"],
+                ["settings", "append", "target.source-map", "/ci runner/build", "/work/my proj"],
+            ]
+        );
+        let create = CommandBuilder::new("target create").arg("/home/me/My Project/app").build();
+        assert_eq!(command::split(&create), ["target", "create", "/home/me/My Project/app"]);
+    }
+
+    #[test]
+    fn strip_path_prefix_appends_one_mapping_per_prefix() {
+        let cfg_in = json!({"stripPathPrefix": ["/home/ci/build/", "", "${HOME}/out"]});
+        assert_eq!(
+            strip_prefix_commands(&cfg_in, &vars(), "/work/proj").unwrap(),
+            [
+                r#"settings append target.source-map "/home/ci/build" "/work/proj""#,
+                r#"settings append target.source-map "/home/me/out" "/work/proj""#,
+            ]
+        );
+        let cfg_in = json!({"stripPathPrefix": "/build"});
+        assert_eq!(
+            strip_prefix_commands(&cfg_in, &vars(), "").unwrap(),
+            [r#"settings append target.source-map "/build" ".""#]
+        );
+        assert_eq!(
+            strip_prefix_commands(&json!({"stripPathPrefix": ["/build", 1]}), &vars(), "").unwrap_err(),
+            "`stripPathPrefix`: has an entry that is not a path\n  value: 1"
+        );
+    }
+
+    #[test]
+    fn field_errors_name_the_key_and_value() {
+        assert_eq!(
+            strip_prefix_commands(&json!({"stripPathPrefix": {}}), &vars(), "").unwrap_err(),
+            "`stripPathPrefix`: not a path or an array of paths\n  value: {}"
+        );
+        assert_eq!(
+            watchpoint_commands(&json!({"watchpoints": [{"address": "0x10", "size": 3}]})).unwrap_err(),
+            "`watchpoints[0].size`: unsupported size\n  value: 3\n  hint: expected 1, 2, 4 or 8"
+        );
+        assert_eq!(
+            breakpoint_commands(&json!({"breakpoints": [{"file": "main.c", "line": 0}]}), &vars(), "").unwrap_err(),
+            "`breakpoints[0].line`: not a positive integer"
+        );
+        assert_eq!(
+            load_address_commands(&json!({"loadOffset": "0xzz"}), Some("app")).unwrap_err(),
+            "`loadOffset`: not an address\n  value: \"0xzz\"\n  hint: expected 0x hex or decimal"
+        );
+        assert_eq!(
+            detach_on_exit(&json!({"terminateDebuggee": "yes"}), StartDebuggingRequestArgumentsRequest::Launch)
+                .unwrap_err(),
+            "`terminateDebuggee`: not a boolean\n  value: \"yes\""
+        );
+        assert_eq!(
+            program_arguments(&json!(["-v", 2]), "args", &vars()).unwrap_err(),
+            "`args[1]`: not a string\n  value: 2"
+        );
+    }
+
+    #[test]
+    fn connect_timeouts_are_whole_seconds() {
+        assert_eq!(first_stop_timeout(&json!({})).unwrap(), None);
+        assert_eq!(first_stop_timeout(&json!({"firstStopTimeout": 30})).unwrap(), Some(30));
+        assert_eq!(first_stop_timeout(&json!({"connectTimeoutMs": 1500})).unwrap(), Some(2));
+        assert!(first_stop_timeout(&json!({"firstStopTimeout": 30, "connectTimeoutMs": 1500})).is_err());
+        assert!(first_stop_timeout(&json!({"connectTimeoutMs": 0})).is_err());
+        assert_eq!(attach_timeout(&json!({"attachTimeout": null})).unwrap(), None);
+        assert_eq!(connect_attempts(&json!({})).unwrap(), 1);
+        assert_eq!(connect_attempts(&json!({"connectRetries": 2})).unwrap(), 3);
+    }
+
+    #[test]
+    fn continue_on_attach_leaves_the_process_running_or_halted() {
+        let cfg_in = json!({"continueOnAttach": true});
+        assert_eq!(continue_on_attach_command(&cfg_in, false, false).unwrap().as_deref(), Some("process continue"));
+        assert!(continue_on_attach_command(&cfg_in, true, false).is_err());
+        let cfg_in = json!({"continueOnAttach": false});
+        assert_eq!(continue_on_attach_command(&cfg_in, true, false).unwrap().as_deref(), Some("process interrupt"));
+        assert!(continue_on_attach_command(&cfg_in, true, true).is_err());
+        assert_eq!(continue_on_attach_command(&json!({}), true, true).unwrap(), None);
+    }
+
+    #[test]
+    fn fork_mode_goes_in_init_commands() {
+        let mut warnings = Vec::new();
+        assert_eq!(
+            fork_commands(&json!({"followForkMode": "child", "detachOnFork": false}), &mut warnings).unwrap(),
+            ["settings set target.process.follow-fork-mode child"]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(fork_commands(&json!({"followForkMode": "both"}), &mut warnings).is_err());
+    }
+
+    #[test]
+    fn custom_request_args_only_replace_generated_fields_when_asked() {
+        let flags = RemoteDebugConfig::parse(&json!({})).unwrap();
+        let mut cfg_out = serde_json::Map::new();
+        cfg_out.insert("request".into(), json!("attach"));
+        let cfg_in = json!({"customRequestArgs": {"request": "launch", "displayExtendedBacktrace": true}});
+        assert_eq!(
+            apply_custom_request_args(&mut cfg_out, &cfg_in, &flags).unwrap_err(),
+            "`customRequestArgs`: sets fields the extension already generates\n  value: request\n  \
+             hint: set `\"customRequestArgsOverride\": true` to replace them"
+        );
+        let flags = RemoteDebugConfig::parse(&json!({"customRequestArgsOverride": true})).unwrap();
+        apply_custom_request_args(&mut cfg_out, &cfg_in, &flags).unwrap();
+        assert_eq!(cfg_out["request"], "launch");
+        assert_eq!(cfg_out["displayExtendedBacktrace"], true);
+    }
+
+    #[test]
+    fn interleaved_sessions_each_get_their_own_config() {
+        use StartDebuggingRequestArgumentsRequest::{Attach, Launch};
+        let mut ext = <Ext as zed::Extension>::new();
+        let board = json!({"request": "attach", "target": "tcp://board:1234"});
+        let local = json!({"request": "launch", "program": "app"});
+        let other = json!({"request": "launch", "program": "other"});
+        assert!(ext.dap_request_kind("lldb-remote".to_string(), board.clone()).unwrap() == Attach);
+        assert!(ext.dap_request_kind("lldb-remote".to_string(), local.clone()).unwrap() == Launch);
+        assert!(ext.dap_request_kind("other-adapter".to_string(), other.clone()).unwrap() == Launch);
+
+        let captured = ext.clear_pending_session("lldb-remote", Some(Fingerprint::of(&board))).unwrap();
+        assert_eq!(captured.json, board.to_string());
+        assert!(captured.request_kind == Attach);
+        let captured = ext.clear_pending_session("other-adapter", None).unwrap();
+        assert_eq!(captured.json, other.to_string());
+        let captured = ext.clear_pending_session("lldb-remote", Some(Fingerprint::of(&local))).unwrap();
+        assert!(captured.request_kind == Launch);
+    }
+
+    #[test]
+    fn a_second_get_dap_binary_finds_nothing_left_over() {
+        let mut ext = <Ext as zed::Extension>::new();
+        let config = json!({"target": "tcp://board:1234"});
+        ext.dap_request_kind("lldb-remote".to_string(), config.clone()).unwrap();
+        let fingerprint = Fingerprint::of(&config);
+        assert_eq!(ext.clear_pending_session("lldb-remote", Some(fingerprint)).unwrap().json, config.to_string());
+        assert_eq!(
+            ext.clear_pending_session("lldb-remote", Some(fingerprint)).err().unwrap(),
+            "`request`: was never determined for this `lldb-remote` session (dap_request_kind was not called)\n  \
+             hint: start the session again"
+        );
+
+        ext.dap_request_kind("lldb-remote".to_string(), config).unwrap();
+        let changed = Fingerprint::of(&json!({"target": "tcp://board:1235"}));
+        assert!(
+            ext.clear_pending_session("lldb-remote", Some(changed))
+                .err()
+                .unwrap()
+                .starts_with("`request`: was determined for a different configuration")
+        );
+    }
+
+    #[test]
+    fn extended_remote_creates_the_target_after_connecting() {
+        let target = || vec![r#"target create "/bin/app""#.to_string()];
+        let connect = || vec!["gdb-remote board:1234".to_string(), "process status".to_string()];
+        assert_eq!(
+            connection_sequence(target(), connect(), false),
+            [r#"target create "/bin/app""#, "gdb-remote board:1234", "process status"]
+        );
+        assert_eq!(
+            connection_sequence(target(), connect(), true),
+            ["gdb-remote board:1234", "process status", r#"target create "/bin/app""#]
+        );
+        assert_eq!(
+            remote_launch_commands(true, false, &["-v".to_string(), "a b".to_string()]),
+            [r#"process launch --stop-at-entry -- "-v" "a b""#]
+        );
+        assert_eq!(
+            remote_launch_commands(true, true, &[]),
+            ["breakpoint set --one-shot true --name main", "process launch"]
+        );
+    }
+
+    fn addr(target: &str) -> std::result::Result<String, String> {
+        parse_target(target).map(|t| t.addr).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn a_target_without_a_scheme_is_tcp() {
+        assert_eq!(addr("192.168.0.5:1234").unwrap(), "192.168.0.5:1234");
+        assert_eq!(addr("tcp://Board.local:1234").unwrap(), "board.local:1234");
+        assert!(parse_target("tcps://board:1234").unwrap().tls);
+        assert_eq!(
+            addr("board").unwrap_err(),
+            "`target`: not a HOST:PORT address\n  value: \"board\"\n  \
+             hint: expected tcp://HOST:PORT or HOST:PORT with a numeric port"
+        );
+        assert!(addr("board:gdb").is_err());
+        assert!(addr(":1234").is_err());
+        assert!(addr("udp://board:1234").unwrap_err().starts_with("`target`: unsupported scheme"));
+    }
+
+    #[test]
+    fn a_target_is_only_host_and_port() {
+        assert_eq!(addr("tcp://board:1234/").unwrap(), "board:1234");
+        assert_eq!(addr("tcp://board:1234").unwrap(), "board:1234");
+        assert_eq!(
+            addr("tcp://board:1234?foo").unwrap_err(),
+            "`target`: unexpected \"?foo\" after the port\n  value: \"tcp://board:1234?foo\"\n  \
+             hint: expected HOST:PORT only"
+        );
+        assert!(addr("board:1234/gdb").unwrap_err().contains("unexpected \"/gdb\" after the port"));
+        assert_eq!(addr("tcp://my%20host:1234").unwrap(), "my host:1234");
+        assert_eq!(addr("tcp://fe80::1:1234").unwrap(), "[fe80::1]:1234");
+    }
+
+    #[test]
+    fn target_addresses_expand_variables() {
+        use StartDebuggingRequestArgumentsRequest::Attach;
+        let vars = Variables {
+            shell_env: vec![("DEBUG_HOST".to_string(), "board.lab".to_string())],
+            ..vars()
+        };
+        let flags = RemoteDebugConfig::parse(&json!({"target": "tcp://${env:DEBUG_HOST}:1234"})).unwrap();
+        let targets = session_targets(&flags, Attach, None, None, false, &vars).unwrap();
+        assert_eq!(targets[0].addr, "board.lab:1234");
+
+        let flags = RemoteDebugConfig::parse(&json!({"target": ["tcp://${env:DEBUG_HOST}:${serverPort}"]})).unwrap();
+        let targets = session_targets(&flags, Attach, None, Some(5000), false, &vars).unwrap();
+        assert_eq!(targets[0].addr, "board.lab:5000");
+    }
+
+    #[test]
+    fn every_target_in_an_array_is_connected_to_in_order() {
+        use StartDebuggingRequestArgumentsRequest::Attach;
+        let targets = |target: Value| {
+            let flags = RemoteDebugConfig::parse(&json!({ "target": target })).unwrap();
+            session_targets(&flags, Attach, None, None, false, &vars())
+                .map(|targets| targets.into_iter().map(|t| t.addr).collect::<Vec<_>>())
+        };
+        assert_eq!(targets(json!(["tcp://board:1234", "board:1235"])).unwrap(), ["board:1234", "board:1235"]);
+        assert_eq!(targets(json!("tcp://board:1234")).unwrap(), ["board:1234"]);
+        assert_eq!(
+            targets(json!(["tcp://board:1234", "board"])).unwrap_err(),
+            "`target[1]`: not a HOST:PORT address\n  value: \"board\"\n  \
+             hint: expected tcp://HOST:PORT or HOST:PORT with a numeric port"
+        );
+        assert!(targets(json!([])).unwrap_err().starts_with("`target`: is an empty array"));
+        assert_eq!(
+            targets(json!(["tcp://board:1234", "tcps://board:1235"])).unwrap_err(),
+            "`target` mixes tcp:// and tcps:// addresses"
+        );
+    }
+
+    #[test]
+    fn trailing_slashes_are_dropped_but_roots_kept() {
+        let cases = [
+            ("/home/alice/proj/", "/home/alice/proj"),
+            ("/home/alice/proj//", "/home/alice/proj"),
+            ("/home/alice/proj", "/home/alice/proj"),
+            ("/", "/"),
+            ("//", "/"),
+            ("C:/", "C:/"),
+            ("C:\\", "C:\\"),
+            ("C:\\src\\", "C:\\src"),
+            ("", ""),
+        ];
+        for (path, normalized) in cases {
+            assert_eq!(normalize_path_trailing_slash(path), normalized, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn only_unix_remote_paths_lose_their_backslashes() {
+        assert_eq!(normalize_remote_path(r"/build\myproject"), "/build/myproject");
+        assert_eq!(normalize_remote_path(r"C:\build\project"), r"C:\build\project");
+        assert_eq!(normalize_remote_path(r"\\remote-share\proj"), r"\\remote-share\proj");
+        assert_eq!(normalize_remote_path("/build/project"), "/build/project");
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_after_variables() {
+        let target = parse_target("tcp://[fe80::1]:1234").unwrap();
+        let placeholders = Placeholders {
+            target: Some(&target),
+            program: None,
+        };
+        assert_eq!(
+            user_commands(
+                &["script print('{target} on {host} port {port}')".to_string(), "log {x} ${HOME}/{target}".to_string()],
+                &vars(),
+                &placeholders,
+            ),
+            ["script print('[fe80::1]:1234 on fe80::1 port 1234')", "log {x} /home/me/[fe80::1]:1234"]
+        );
+        let none = Placeholders {
+            target: None,
+            program: None,
+        };
+        assert_eq!(substitute_placeholders("echo {target} {port}", &none), "echo {target} {port}");
+    }
+
+    #[test]
+    fn program_placeholder_is_left_as_written_without_a_program() {
+        let with_program = Placeholders {
+            target: None,
+            program: Some("/home/me/My App/app"),
+        };
+        assert_eq!(
+            prepare_command("add-dsym {program}.dSYM", &vars(), &with_program),
+            "add-dsym /home/me/My App/app.dSYM"
+        );
+        let without = Placeholders {
+            target: None,
+            program: None,
+        };
+        assert_eq!(prepare_command("add-dsym {program}.dSYM", &vars(), &without), "add-dsym {program}.dSYM");
+
+        let flags = RemoteDebugConfig::parse(&json!({"postRunCommands": ["add-dsym {program}.dSYM"]})).unwrap();
+        assert!(unfilled_program_warning(&flags).is_some());
+        let flags = RemoteDebugConfig::parse(&json!({"postRunCommands": ["bt"]})).unwrap();
+        assert_eq!(unfilled_program_warning(&flags), None);
+    }
+
+    #[test]
+    fn command_files_written_on_windows_read_the_same() {
+        let unix = "settings set target.x86-disassembly-flavor intel\n\ngdb-remote board:1234\n";
+        let windows = "\u{feff}settings set target.x86-disassembly-flavor intel  \r\n\r\ngdb-remote board:1234\t\r\n";
+        assert_eq!(
+            parse_command_file(unix),
+            ["settings set target.x86-disassembly-flavor intel", "gdb-remote board:1234"]
+        );
+        assert_eq!(parse_command_file(windows), parse_command_file(unix));
+        assert!(parse_command_file("\u{feff}").is_empty());
+    }
+
+    /// A worktree whose PATH holds `paths`, and whose host runs `runs`
+    struct FakeWorktree {
+        paths: &'static [&'static str],
+        runs: &'static [&'static str],
+    }
+
+    impl AdapterLookup for FakeWorktree {
+        fn which(&self, command: &str) -> Option<String> {
+            self.paths.iter().find(|p| p.ends_with(&format!("/{}", command))).map(|p| p.to_string())
+        }
+
+        fn runs(&self, command: &str) -> bool {
+            self.runs.contains(&command)
+        }
+    }
+
+    /// A host where only `files` exist
+    struct FakeFiles(&'static [&'static str]);
+
+    impl host::FileCheck for FakeFiles {
+        fn is_file(&self, path: &str) -> Result<bool> {
+            Ok(self.0.contains(&path))
+        }
+    }
+
+    #[test]
+    fn the_init_file_is_sourced_from_the_worktree() {
+        let init = |cfg_in: Value, files: &FakeFiles| {
+            let flags = RemoteDebugConfig::parse(&cfg_in).unwrap();
+            init_file_command(&cfg_in, &flags, &vars(), "/work", files)
+        };
+        let files = FakeFiles(&["/home/me/my init", "/work/lldb/init"]);
+        assert_eq!(
+            init(json!({"lldbInitFile": "${HOME}/my init"}), &files).unwrap().as_deref(),
+            Some(r#"command source "/home/me/my init""#)
+        );
+        assert_eq!(
+            init(json!({"lldbInitFile": "lldb/init", "strictPaths": true}), &files).unwrap().as_deref(),
+            Some(r#"command source "/work/lldb/init""#)
+        );
+        assert_eq!(init(json!({}), &files).unwrap(), None);
+        let e = init(json!({"lldbInitFile": "missing", "strictPaths": true}), &files).unwrap_err();
+        assert_eq!(e, "`lldbInitFile` not found: /work/missing");
+        // Without strictPaths LLDB reports a missing file itself
+        assert!(init(json!({"lldbInitFile": "missing"}), &files).unwrap().is_some());
+    }
+
+    fn program(cfg_in: Value, files: &FakeFiles) -> Result<Option<String>> {
+        let flags = RemoteDebugConfig::parse(&cfg_in).unwrap();
+        configured_program(&cfg_in, &flags, "/work", &vars(), files)
+    }
+
+    #[test]
+    fn the_first_program_candidate_that_exists_wins() {
+        let files = FakeFiles(&["/out/arm64/app", "/out/x86_64/app"]);
+        let candidates = json!({"program": ["/out/universal/app", "/out/arm64/app", "/out/x86_64/app"]});
+        assert_eq!(program(candidates, &files).unwrap().as_deref(), Some("/out/arm64/app"));
+        let e = program(json!({"program": ["/opt/a", "${HOME}/b"]}), &files).unwrap_err();
+        assert_eq!(e, "none of the `program` candidates exist: /opt/a, /home/me/b");
+    }
+
+    #[test]
+    fn a_relative_program_is_found_under_program_base_dir() {
+        let files = FakeFiles(&["/work/app", "/work/build/app"]);
+        let base = |program: &str| json!({"program": program, "programBaseDir": "${HOME}/out"});
+        assert_eq!(program(base("bin/app"), &files).unwrap().as_deref(), Some("/home/me/out/bin/app"));
+        assert_eq!(program(base("/opt/app"), &files).unwrap().as_deref(), Some("/opt/app"));
+        let relative_base = json!({"program": "app", "programBaseDir": "build"});
+        assert_eq!(program(relative_base, &files).unwrap().as_deref(), Some("/work/build/app"));
+        // Without a base, lldb-dap resolves a lone program against its cwd,
+        // the worktree root by default; candidates are checked from the root
+        assert_eq!(program(json!({"program": "app"}), &files).unwrap().as_deref(), Some("app"));
+        let candidates = json!({"program": ["missing/app", "app"]});
+        assert_eq!(program(candidates, &files).unwrap().as_deref(), Some("/work/app"));
+    }
+
+    #[test]
+    fn the_adapter_is_found_through_the_worktree_first() {
+        let resolve = |cfg_in: Value, worktree: &FakeWorktree, warnings: &mut Vec<String>| {
+            resolve_adapter_command(&cfg_in, worktree, &vars(), "/work", warnings)
+        };
+        let nix = FakeWorktree {
+            paths: &["/nix/store/llvm/bin/lldb-dap-20", "/nix/store/llvm/bin/lldb-dap"],
+            runs: &[],
+        };
+        let host = FakeWorktree {
+            paths: &[],
+            runs: &["lldb-dap-20"],
+        };
+        let none = FakeWorktree { paths: &[], runs: &[] };
+        let mut warnings = Vec::new();
+
+        assert_eq!(resolve(json!({}), &nix, &mut warnings).unwrap(), "/nix/store/llvm/bin/lldb-dap-20");
+        assert_eq!(resolve(json!({}), &host, &mut warnings).unwrap(), "lldb-dap-20");
+        assert!(resolve(json!({}), &none, &mut warnings).unwrap_err().contains("set `adapterPath`"));
+        assert_eq!(
+            resolve(json!({"adapterPath": "bin/lldb-dap"}), &none, &mut warnings).unwrap(),
+            "/work/bin/lldb-dap"
+        );
+        assert!(warnings.is_empty());
+
+        assert_eq!(
+            resolve(json!({"lldbDapVersion": 18}), &nix, &mut warnings).unwrap(),
+            "/nix/store/llvm/bin/lldb-dap"
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(resolve(json!({"lldbDapVersion": 18}), &none, &mut warnings).is_err());
+    }
+
+    #[test]
+    fn reads_the_major_version_from_version_output() {
+        let cases = [
+            ("lldb version 20.1.2", Some(20)),
+            ("lldb-dap version 18.1.8 (https://github.com/llvm/llvm-project 3b5b5c1e)", Some(18)),
+            ("lldb version 17.0.6\n  clang revision 6009708b", Some(17)),
+            ("Ubuntu LLVM version 19.1.1\n  Optimized build.", Some(19)),
+            ("lldb version unknown", None),
+            ("", None),
+        ];
+        for (output, major) in cases {
+            assert_eq!(parse_lldb_version(output), major, "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn cwd_defaults_to_the_worktree_root() {
+        assert_eq!(adapter_cwd(&json!({}), &vars(), "/work").unwrap().as_deref(), Some("/work"));
+        assert_eq!(adapter_cwd(&json!({}), &vars(), "").unwrap(), None);
+        assert_eq!(adapter_cwd(&json!({"cwd": "build"}), &vars(), "/work").unwrap().as_deref(), Some("/work/build"));
+        assert_eq!(
+            adapter_cwd(&json!({"cwd": "${HOME}/run"}), &vars(), "/work").unwrap().as_deref(),
+            Some("/home/me/run")
+        );
+        assert_eq!(adapter_cwd(&json!({"cwd": null}), &vars(), "/work").unwrap(), None);
+        assert_eq!(
+            adapter_cwd(&json!({"cwd": 1}), &vars(), "/work").unwrap_err(),
+            "`cwd`: not a path or null\n  value: 1"
+        );
+    }
+
+    #[test]
+    fn breakpoints_are_set_by_file_and_line_or_function() {
+        let cfg_in = json!({"breakpoints": [
+            {"file": "src/main.c", "line": 42},
+            {"file": "${HOME}/lib/util.c", "line": 7},
+            {"function": "main"},
+        ]});
+        assert_eq!(
+            breakpoint_commands(&cfg_in, &vars(), "/work").unwrap(),
+            [
+                r#"breakpoint set --file "/work/src/main.c" --line 42"#,
+                r#"breakpoint set --file "/home/me/lib/util.c" --line 7"#,
+                r#"breakpoint set --name "main""#,
+            ]
+        );
+        assert_eq!(breakpoint_commands(&json!({}), &vars(), "/work").unwrap(), Vec::<String>::new());
+        assert_eq!(
+            breakpoint_commands(&json!({"breakpoints": [{"line": 3}]}), &vars(), "/work").unwrap_err(),
+            "`breakpoints[0]` needs either `file` and `line`, or `function`"
+        );
+        assert!(breakpoint_commands(&json!({"breakpoints": [{"function": ""}]}), &vars(), "/work").is_err());
+    }
+
+    #[test]
+    fn an_unusual_source_language_is_passed_on_with_a_warning() {
+        let mut warnings = Vec::new();
+        assert_eq!(
+            source_language_command(&json!({"sourceLanguage": "rust"}), &mut warnings).unwrap().as_deref(),
+            Some("settings set target.language rust")
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(
+            source_language_command(&json!({"sourceLanguage": "fortran"}), &mut warnings).unwrap().as_deref(),
+            Some("settings set target.language fortran")
+        );
+        assert_eq!(
+            warnings,
+            ["sourceLanguage \"fortran\" is not one of c, c++, objective-c, objective-c++, rust, swift; \
+              passing it to LLDB as is"]
+        );
+        assert!(source_language_command(&json!({"sourceLanguage": ""}), &mut warnings).is_err());
+        assert_eq!(source_language_command(&json!({}), &mut warnings).unwrap(), None);
+    }
+
+    #[test]
+    fn without_a_worktree_root_relative_paths_stay_relative() {
+        assert_eq!(resolve_worktree_path("build/app", ""), "build/app");
+        assert_eq!(resolve_worktree_path("/opt/app", ""), "/opt/app");
+        assert_eq!(resolve_worktree_path("build/app", "/work/"), "/work/build/app");
+        assert_eq!(adapter_cwd(&json!({}), &vars(), "").unwrap(), None);
+        assert_eq!(adapter_cwd(&json!({"cwd": "run"}), &vars(), "").unwrap().as_deref(), Some("run"));
+        assert_eq!(
+            breakpoint_commands(&json!({"breakpoints": [{"file": "main.c", "line": 1}]}), &vars(), "").unwrap(),
+            [r#"breakpoint set --file "main.c" --line 1"#]
+        );
+    }
+
+    #[test]
+    fn architecture_adds_arch_to_target_create() {
+        let arch = architecture(&json!({"architecture": " arm64 "})).unwrap();
+        assert_eq!(arch.as_deref(), Some("arm64"));
+        assert_eq!(
+            target_create(arch.as_deref()).arg("/bin/app").build(),
+            r#"target create --arch "arm64" "/bin/app""#
+        );
+        assert_eq!(architecture(&json!({})).unwrap(), None);
+        assert_eq!(target_create(None).arg("/bin/app").build(), r#"target create "/bin/app""#);
+        assert!(architecture(&json!({"architecture": " "})).is_err());
+    }
+
+    #[test]
+    fn monitor_commands_are_checked_like_other_commands() {
+        let monitor = |cfg_in: Value| {
+            RemoteDebugConfig::parse(&cfg_in).map(|flags| monitor_commands(&cfg_in, &flags))
+        };
+        assert_eq!(
+            monitor(json!({"resetAfterConnect": true, "monitorCommands": "reset halt\n arm semihosting enable "})).unwrap(),
+            [
+                "process plugin packet monitor reset halt",
+                "process plugin packet monitor arm semihosting enable",
+            ]
+        );
+        let e = monitor(json!({"monitorCommands": ["reset", 1, null]})).unwrap_err();
+        assert!(e.starts_with("`monitorCommands`: has entries that are not strings\n  value: [1] is a number, [2] is null"), "{}", e);
+        let lenient = json!({"monitorCommands": ["reset", 1], "lenientCommands": true});
+        assert_eq!(monitor(lenient).unwrap(), ["process plugin packet monitor reset"]);
+        assert!(monitor(json!({"monitorCommands": {"reset": true}})).unwrap_err().contains("not a list of commands"));
+    }
+
+    #[test]
+    fn connection_is_verified_only_when_asked() {
+        let cfg_in = json!({"monitorCommands": ["reset halt"]});
+        let flags = RemoteDebugConfig::parse(&cfg_in).unwrap();
+        let cmds = post_connect_commands(&cfg_in, &flags, None).unwrap();
+        assert!(!cmds.iter().any(|c| c == "process status"));
+
+        let cfg_in = json!({"monitorCommands": ["reset halt"], "verifyConnection": true});
+        let flags = RemoteDebugConfig::parse(&cfg_in).unwrap();
+        let verified = post_connect_commands(&cfg_in, &flags, None).unwrap();
+        assert_eq!(verified.last().map(String::as_str), Some("process status"));
+        assert_eq!(verified[..verified.len() - 1], cmds);
+    }
+
+    fn after_attach(cfg_in: Value, attached: bool) -> Result<Vec<String>> {
+        after_attach_commands(&cfg_in, &RemoteDebugConfig::parse(&cfg_in).unwrap(), attached)
+    }
+
+    #[test]
+    fn break_main_runs_an_attached_process_to_main() {
+        assert_eq!(
+            after_attach(json!({"stopOnEntry": true, "stopOnEntryBreakMain": true}), true).unwrap(),
+            ["breakpoint set --one-shot true --name main", "process continue"]
+        );
+        assert!(after_attach(json!({"stopOnEntry": true}), true).unwrap().is_empty());
+        assert!(after_attach(json!({"stopOnEntryBreakMain": true}), true).unwrap().is_empty());
+        // The stub's own launch runs to `main` in extended-remote mode
+        assert!(after_attach(json!({"stopOnEntry": true, "stopOnEntryBreakMain": true}), false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn continue_on_attach_comes_last() {
+        assert_eq!(after_attach(json!({"continueOnAttach": true}), true).unwrap(), ["process continue"]);
+        assert_eq!(
+            after_attach(json!({"continueOnAttach": false, "stopOnEntry": true}), true).unwrap(),
+            ["process interrupt"]
+        );
+        assert!(after_attach(json!({}), true).unwrap().is_empty());
+        assert_eq!(
+            after_attach(json!({"continueOnAttach": true, "stopOnEntry": true}), true).unwrap_err(),
+            "`continueOnAttach` and `stopOnEntry` cannot both be true"
+        );
+        assert!(
+            after_attach(json!({"continueOnAttach": false, "stopOnEntry": true, "stopOnEntryBreakMain": true}), true)
+                .unwrap_err()
+                .contains("`stopOnEntryBreakMain`")
+        );
+    }
+
+    #[test]
+    fn disable_stdio_redirect_only_changes_launches() {
+        use StartDebuggingRequestArgumentsRequest::{Attach, Launch};
+        let flags = RemoteDebugConfig::parse(&json!({"disableStdioRedirect": true})).unwrap();
+        let mut warnings = Vec::new();
+        assert_eq!(terminal_field(&flags, Launch, &mut warnings), Some(("runInTerminal", Value::Bool(true))));
+        assert!(warnings.is_empty());
+        assert_eq!(terminal_field(&flags, Attach, &mut warnings), None);
+        assert_eq!(warnings, ["disableStdioRedirect only applies to launch requests"]);
+
+        let flags = RemoteDebugConfig::parse(&json!({})).unwrap();
+        assert_eq!(terminal_field(&flags, Launch, &mut warnings), None);
+    }
+
+    #[test]
+    fn detach_on_exit_joins_the_users_exit_commands_on_the_command_path() {
+        let placeholders = Placeholders {
+            target: None,
+            program: Some("/bin/app"),
+        };
+        let exit = |cfg_in: Value, detach: Option<bool>, command_driven: bool| {
+            let flags = RemoteDebugConfig::parse(&cfg_in).unwrap();
+            exit_commands(&flags, detach, command_driven, &vars(), &placeholders)
+        };
+        let user = json!({"exitCommands": ["log disable gdb-remote packets", "platform shell rm {program}.lock"]});
+        assert_eq!(
+            exit(user.clone(), Some(false), true),
+            ["log disable gdb-remote packets", "platform shell rm /bin/app.lock", "process kill"]
+        );
+        assert_eq!(exit(json!({}), Some(true), true), ["detach"]);
+        assert_eq!(exit(json!({"exitCommands": ["detach"]}), Some(true), true), ["detach"]);
+        // A plain launch has lldb-dap end the process itself, by terminateDebuggee
+        assert!(exit(json!({}), Some(false), false).is_empty());
+        assert_eq!(exit(user, Some(false), false).len(), 2);
+        assert!(exit(json!({}), None, true).is_empty());
+    }
+
+    #[test]
+    fn pre_run_commands_are_forwarded_apart_from_init_commands() {
+        let flags = RemoteDebugConfig::parse(&json!({
+            "initCommands": ["settings set target.language rust"],
+            "preRunCommands": ["target modules add ${HOME}/lib.so"],
+        }))
+        .unwrap();
+        let placeholders = Placeholders {
+            target: None,
+            program: None,
+        };
+        assert_eq!(
+            run_command_fields(&flags, &vars(), &placeholders),
+            [("preRunCommands".to_string(), json!(["target modules add /home/me/lib.so"]))]
+        );
+    }
+
+    #[test]
+    fn post_run_commands_are_forwarded_with_placeholders() {
+        let flags = RemoteDebugConfig::parse(&json!({
+            "attachCommands": ["process status"],
+            "postRunCommands": "add-dsym {program}.dSYM\nprocess continue",
+        }))
+        .unwrap();
+        let placeholders = Placeholders {
+            target: None,
+            program: Some("/bin/app"),
+        };
+        assert_eq!(
+            run_command_fields(&flags, &vars(), &placeholders),
+            [("postRunCommands".to_string(), json!(["add-dsym /bin/app.dSYM", "process continue"]))]
+        );
+    }
+}
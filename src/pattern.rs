@@ -0,0 +1,415 @@
+//! A small regular expression matcher, for patterns in the config that the
+//! extension applies itself rather than handing to `grep` on the host.
+//!
+//! Supported: literals, `.`, `[...]` classes with ranges and `^` negation,
+//! `\d \w \s` (and `\D \W \S`), escaped metacharacters, `(...)` groups,
+//! `|`, the greedy quantifiers `* + ? {n} {n,} {n,m}`, and `^ $` anchors.
+
+use zed_extension_api::Result;
+
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Group(Box<Node>, usize),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+/// A compiled pattern
+pub(crate) struct Pattern {
+    root: Node,
+    groups: usize,
+}
+
+/// Capture spans of one match: the whole match first, then each group
+type Captures = Vec<Option<(usize, usize)>>;
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unbalanced `)` at offset {}", parser.pos));
+        }
+        Ok(Self {
+            root,
+            groups: parser.groups,
+        })
+    }
+
+    /// The leftmost match in `text`: the whole match, then each group that took part
+    pub(crate) fn captures(&self, text: &str) -> Option<Vec<Option<String>>> {
+        let input: Vec<char> = text.chars().collect();
+        for start in 0..=input.len() {
+            let mut caps: Captures = vec![None; self.groups + 1];
+            let mut end = None;
+            if matches(&self.root, &input, start, &mut caps, &mut |pos, _| {
+                end = Some(pos);
+                true
+            }) {
+                caps[0] = end.map(|end| (start, end));
+                return Some(
+                    caps.iter()
+                        .map(|span| span.map(|(a, b)| input[a..b].iter().collect()))
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+}
+
+/// Match `node` at `pos`, then hand the end position to `next`, backtracking
+/// into `node` while `next` fails
+fn matches(
+    node: &Node,
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    match node {
+        Node::Char(c) => input.get(pos) == Some(c) && next(pos + 1, caps),
+        Node::Any => pos < input.len() && input[pos] != '\n' && next(pos + 1, caps),
+        Node::Class { ranges, negated } => match input.get(pos) {
+            Some(c) if ranges.iter().any(|(lo, hi)| lo <= c && c <= hi) != *negated => next(pos + 1, caps),
+            _ => false,
+        },
+        Node::Start => pos == 0 && next(pos, caps),
+        Node::End => pos == input.len() && next(pos, caps),
+        Node::Group(inner, index) => {
+            let start = pos;
+            matches(inner, input, pos, caps, &mut |end, caps| {
+                let saved = caps[*index];
+                caps[*index] = Some((start, end));
+                next(end, caps) || {
+                    caps[*index] = saved;
+                    false
+                }
+            })
+        }
+        Node::Concat(nodes) => concat(nodes, input, pos, caps, next),
+        Node::Alt(branches) => branches
+            .iter()
+            .any(|branch| matches(branch, input, pos, caps, next)),
+        Node::Repeat { node, min, max } => repeat(node, *min, *max, 0, input, pos, caps, next),
+    }
+}
+
+fn concat(
+    nodes: &[Node],
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    match nodes.split_first() {
+        None => next(pos, caps),
+        Some((first, rest)) => matches(first, input, pos, caps, &mut |end, caps| {
+            concat(rest, input, end, caps, next)
+        }),
+    }
+}
+
+/// Greedy repetition: as many as possible, giving them back one at a time
+#[allow(clippy::too_many_arguments)]
+fn repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    input: &[char],
+    pos: usize,
+    caps: &mut Captures,
+    next: &mut dyn FnMut(usize, &mut Captures) -> bool,
+) -> bool {
+    if max.is_none_or(|max| count < max)
+        && matches(node, input, pos, caps, &mut |end, caps| {
+            // An empty iteration past the minimum would loop forever
+            (end != pos || count < min) && repeat(node, min, max, count + 1, input, end, caps, next)
+        })
+    {
+        return true;
+    }
+    count >= min && next(pos, caps)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn alternation(&mut self) -> Result<Node> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn quantified(&mut self, node: Node) -> Result<Node> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return self.braces(node),
+            _ => return Ok(node),
+        };
+        self.pos += 1;
+        if matches!(node, Node::Start | Node::End) {
+            return Err(format!("nothing to repeat at offset {}", self.pos - 1));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+
+    /// `{n}`, `{n,}` or `{n,m}`
+    fn braces(&mut self, node: Node) -> Result<Node> {
+        let open = self.pos;
+        let close = self.chars[open..]
+            .iter()
+            .position(|c| *c == '}')
+            .map(|i| open + i)
+            .ok_or_else(|| format!("unclosed `{{` at offset {}", open))?;
+        let body: String = self.chars[open + 1..close].iter().collect();
+        let number = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| format!("invalid repetition {{{}}} at offset {}", body, open))
+        };
+        let (min, max) = match body.split_once(',') {
+            None => (number(&body)?, Some(number(&body)?)),
+            Some((min, "")) => (number(min)?, None),
+            Some((min, max)) => (number(min)?, Some(number(max)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid repetition {{{}}} at offset {}", body, open));
+        }
+        self.pos = close + 1;
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let offset = self.pos;
+        match self.bump() {
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('(') => {
+                if self.peek() == Some('?') {
+                    return Err(format!("`(?` groups are not supported (offset {})", offset));
+                }
+                self.groups += 1;
+                let index = self.groups;
+                let inner = self.alternation()?;
+                if self.bump() != Some(')') {
+                    return Err(format!("unclosed `(` at offset {}", offset));
+                }
+                Ok(Node::Group(Box::new(inner), index))
+            }
+            Some('[') => self.class(offset),
+            Some('\\') => self.escape(offset),
+            Some(c @ ('*' | '+' | '?' | '{')) => {
+                Err(format!("nothing to repeat before `{}` at offset {}", c, offset))
+            }
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("pattern ends unexpectedly".to_string()),
+        }
+    }
+
+    fn escape(&mut self, offset: usize) -> Result<Node> {
+        let class = |ranges: &[(char, char)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        Ok(match self.bump() {
+            Some('d') => class(DIGIT, false),
+            Some('D') => class(DIGIT, true),
+            Some('w') => class(WORD, false),
+            Some('W') => class(WORD, true),
+            Some('s') => class(SPACE, false),
+            Some('S') => class(SPACE, true),
+            Some('n') => Node::Char('\n'),
+            Some('t') => Node::Char('\t'),
+            Some(c) if !c.is_ascii_alphanumeric() => Node::Char(c),
+            Some(c) => return Err(format!("unsupported escape `\\{}` at offset {}", c, offset)),
+            None => return Err("pattern ends with `\\`".to_string()),
+        })
+    }
+
+    /// A bracket expression; `offset` is the position of its `[`
+    fn class(&mut self, offset: usize) -> Result<Node> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.bump() {
+                None => return Err(format!("unclosed `[` at offset {}", offset)),
+                // A leading `]` is a literal
+                Some(']') if !first => break,
+                Some('\\') => match self.bump() {
+                    Some('d') => {
+                        ranges.extend_from_slice(DIGIT);
+                        first = false;
+                        continue;
+                    }
+                    Some('w') => {
+                        ranges.extend_from_slice(WORD);
+                        first = false;
+                        continue;
+                    }
+                    Some('s') => {
+                        ranges.extend_from_slice(SPACE);
+                        first = false;
+                        continue;
+                    }
+                    Some(c) => c,
+                    None => return Err(format!("unclosed `[` at offset {}", offset)),
+                },
+                Some(c) => c,
+            };
+            first = false;
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                self.pos += 1;
+                let hi = self.bump().unwrap();
+                if hi < c {
+                    return Err(format!("invalid range {}-{} at offset {}", c, hi, offset));
+                }
+                ranges.push((c, hi));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        Pattern::new(pattern).unwrap().captures(text).map(|caps| caps[0].clone().unwrap())
+    }
+
+    #[test]
+    fn finds_the_leftmost_longest_match() {
+        let cases = [
+            // Anchors
+            ("^port", "port 1234", Some("port")),
+            ("^port", "a port", None),
+            ("1234$", "port 1234", Some("1234")),
+            ("^$", "", Some("")),
+            ("^a$", "ab", None),
+            // Classes
+            ("[0-9]+", "port 1234", Some("1234")),
+            ("[^ ]+$", "port 1234", Some("1234")),
+            ("[]x]", "a]", Some("]")),
+            ("[a-]+", "b-a-", Some("-a-")),
+            (r"\d+", "pid=42", Some("42")),
+            (r"\w+", "  lldb_dap ", Some("lldb_dap")),
+            (r"\S+\s\S+", "Listening on port 5", Some("Listening on")),
+            (r"[\d.]+", "v1.2.3", Some("1.2.3")),
+            (".", "\nx", Some("x")),
+            // Repetition
+            ("a*", "bbb", Some("")),
+            ("ba?r", "br bar", Some("br")),
+            ("a{2}", "aaa", Some("aa")),
+            ("a{2,}", "aaaa", Some("aaaa")),
+            ("a{1,2}b", "aaab", Some("aab")),
+            ("(ab)+", "xababx", Some("abab")),
+            ("(a*)*b", "aab", Some("aab")),
+            ("on|port", "listening on port", Some("on")),
+            // Escaping
+            (r"\.", "a.b", Some(".")),
+            (r"\$\(pid\)", "echo $(pid)", Some("$(pid)")),
+            (r"a\{2\}", "a{2}", Some("a{2}")),
+            (r"\\", r"C:\x", Some(r"\")),
+            (r"\t", "a\tb", Some("\t")),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(find(pattern, text).as_deref(), expected, "{:?} in {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn captures_the_groups_that_took_part() {
+        let pattern = Pattern::new(r"port (\d+)|pipe (\S+)").unwrap();
+        assert_eq!(
+            pattern.captures("listening on port 5000"),
+            Some(vec![Some("port 5000".to_string()), Some("5000".to_string()), None])
+        );
+        assert_eq!(pattern.captures("nothing here"), None);
+    }
+
+    #[test]
+    fn rejects_what_it_does_not_support() {
+        let cases = [
+            ("(a", "unclosed `(` at offset 0"),
+            ("a)", "unbalanced `)` at offset 1"),
+            ("[a", "unclosed `[` at offset 0"),
+            ("[z-a]", "invalid range z-a at offset 0"),
+            ("*a", "nothing to repeat before `*` at offset 0"),
+            ("^*", "nothing to repeat at offset 1"),
+            ("a{3,1}", "invalid repetition {3,1} at offset 1"),
+            ("a{x}", "invalid repetition {x} at offset 1"),
+            ("a{2", "unclosed `{` at offset 1"),
+            ("(?:a)", "`(?` groups are not supported (offset 0)"),
+            (r"\q", r"unsupported escape `\q` at offset 0"),
+            ("a\\", "pattern ends with `\\`"),
+        ];
+        for (pattern, error) in cases {
+            assert_eq!(Pattern::new(pattern).err().as_deref(), Some(error), "{:?}", pattern);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Checks run before the adapter starts (`preflight`)
+
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use zed_extension_api::{Result, process::Command};
+
+use crate::error::ConfigError;
+use crate::host;
+
+/// Seconds a reachability probe waits when `preflight.timeout` is not set
+const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 2;
+
+/// Milliseconds between `connectionProbeCommand` runs when `probeInterval` is not set
+const DEFAULT_PROBE_INTERVAL_MS: u64 = 500;
+
+/// Runs of `connectionProbeCommand` when `probeMaxAttempts` is not set
+const DEFAULT_PROBE_MAX_ATTEMPTS: u64 = 20;
+
+/// How long `waitForServer` waits for the stub, and how often it checks, in ms
+const DEFAULT_WAIT_TIMEOUT_MS: u64 = 15000;
+const DEFAULT_WAIT_INTERVAL_MS: u64 = 250;
+
+/// Checks whether something accepts TCP connections at an address
+pub(crate) trait Probe {
+    fn probe(&self, host: &str, port: u16, timeout_secs: u64) -> Result<()>;
+}
+
+/// Probes with `nc -z`, since the extension can't open sockets itself
+pub(crate) struct NetcatProbe;
+
+impl Probe for NetcatProbe {
+    fn probe(&self, host: &str, port: u16, timeout_secs: u64) -> Result<()> {
+        let output = Command::new("nc")
+            .args(["-z", "-w", &timeout_secs.to_string(), host, &port.to_string()])
+            .output()
+            .map_err(|e| format!("failed to run `nc` for `preflight`: {}", e))?;
+        if output.status == Some(0) {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+    }
+}
+
+/// Split a `HOST:PORT` target address, unbracketing IPv6 hosts
+pub(crate) fn split_address(addr: &str) -> Result<(&str, u16)> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| format!("target address {:?} has no port", addr))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("target address {:?} has an invalid port", addr))?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    Ok((host, port))
+}
+
+/// Run the checks of the `preflight` block against the target addresses
+pub(crate) fn run(preflight: &Value, addrs: &[&str], probe: &dyn Probe) -> Result<()> {
+    if preflight.get("checkRemoteReachable").and_then(|v| v.as_bool()) != Some(true) {
+        return Ok(());
+    }
+    let timeout = match preflight.get("timeout") {
+        None | Some(Value::Null) => DEFAULT_PROBE_TIMEOUT_SECS,
+        Some(v) => v.as_u64().filter(|t| *t > 0).ok_or_else(|| {
+            ConfigError::new("preflight.timeout", "not a number of seconds").value(v)
+        })?,
+    };
+    for addr in addrs {
+        let (host, port) = split_address(addr)?;
+        probe.probe(host, port, timeout).map_err(|e| {
+            let reason = if e.is_empty() { String::new() } else { format!(": {}", e) };
+            format!(
+                "nothing is listening at {} (checked by `preflight`){}; is the debug server running?",
+                addr, reason
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Run `command` with `sh -c` until it succeeds, every `probeInterval` ms and at
+/// most `probeMaxAttempts` times, for a stub that is still coming up
+pub(crate) fn wait_for_command(cfg_in: &Value, command: &str) -> Result<()> {
+    let setting = |key: &str, default: u64, expected: &str| match cfg_in.get(key) {
+        None | Some(Value::Null) => Ok(default),
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| ConfigError::new(key, format!("not {}", expected)).value(v)),
+    };
+    let interval = setting("probeInterval", DEFAULT_PROBE_INTERVAL_MS, "a number of milliseconds")?;
+    let max_attempts = setting("probeMaxAttempts", DEFAULT_PROBE_MAX_ATTEMPTS, "a positive integer")?;
+    if max_attempts == 0 {
+        return Err(ConfigError::new("probeMaxAttempts", "not a positive integer").value(0).into());
+    }
+
+    let mut last_error = String::new();
+    for attempt in 1..=max_attempts {
+        if attempt > 1 {
+            std::thread::sleep(Duration::from_millis(interval));
+        }
+        let output = Command::new("sh")
+            .args(["-c", command])
+            .output()
+            .map_err(|e| format!("failed to run `connectionProbeCommand`: {}", e))?;
+        if output.status == Some(0) {
+            return Ok(());
+        }
+        last_error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    }
+    let reason = if last_error.is_empty() { String::new() } else { format!(": {}", last_error) };
+    Err(format!(
+        "`connectionProbeCommand` {:?} did not succeed after {} attempts{}; is the debug server running?",
+        command, max_attempts, reason
+    ))
+}
+
+/// What `waitForServer` waits for to appear
+pub(crate) enum Endpoint<'a> {
+    /// A `HOST:PORT` that must accept TCP connections
+    Tcp(&'a str),
+    /// A named pipe or socket file that must exist
+    Path(&'a str),
+}
+
+/// Wait until each endpoint is up, for `waitForServer`: `true` for the defaults
+/// or `{"timeoutMs": ..., "intervalMs": ...}`
+pub(crate) fn wait_for_server(wait: &Value, endpoints: &[Endpoint], probe: &dyn Probe) -> Result<()> {
+    let setting = |key: &str, default: u64| match wait.get(key) {
+        None | Some(Value::Null) => Ok(default),
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| ConfigError::new(format!("waitForServer.{}", key), "not a number of milliseconds")
+                .value(v)),
+    };
+    let (timeout, interval) = match wait {
+        Value::Null | Value::Bool(false) => return Ok(()),
+        Value::Bool(true) => (DEFAULT_WAIT_TIMEOUT_MS, DEFAULT_WAIT_INTERVAL_MS),
+        Value::Object(_) => (
+            setting("timeoutMs", DEFAULT_WAIT_TIMEOUT_MS)?,
+            setting("intervalMs", DEFAULT_WAIT_INTERVAL_MS)?,
+        ),
+        v => {
+            return Err(ConfigError::new("waitForServer", "not true or an object")
+                .value(v)
+                .hint("expected true or {\"timeoutMs\": ..., \"intervalMs\": ...}")
+                .into());
+        }
+    };
+
+    let start = Instant::now();
+    for endpoint in endpoints {
+        loop {
+            let (up, what) = match endpoint {
+                Endpoint::Tcp(addr) => {
+                    let (host, port) = split_address(addr)?;
+                    (probe.probe(host, port, 1).is_ok(), *addr)
+                }
+                Endpoint::Path(path) => (host::exists(path)?, *path),
+            };
+            if up {
+                break;
+            }
+            let waited = start.elapsed();
+            if waited >= Duration::from_millis(timeout) {
+                return Err(format!(
+                    "{} did not come up within {} ms (waited {} ms for `waitForServer`); is the debug server running?",
+                    what,
+                    timeout,
+                    waited.as_millis()
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(interval));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Records what it was asked to probe; only `up` accepts connections
+    struct FakeProbe {
+        up: &'static [(&'static str, u16)],
+        probed: RefCell<Vec<(String, u16, u64)>>,
+    }
+
+    impl Probe for FakeProbe {
+        fn probe(&self, host: &str, port: u16, timeout_secs: u64) -> Result<()> {
+            self.probed.borrow_mut().push((host.to_string(), port, timeout_secs));
+            if self.up.contains(&(host, port)) {
+                Ok(())
+            } else {
+                Err("Connection refused".to_string())
+            }
+        }
+    }
+
+    fn probe(up: &'static [(&'static str, u16)]) -> FakeProbe {
+        FakeProbe {
+            up,
+            probed: RefCell::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn splits_addresses_for_the_probe() {
+        assert_eq!(split_address("board:1234").unwrap(), ("board", 1234));
+        assert_eq!(split_address("[fe80::1]:1234").unwrap(), ("fe80::1", 1234));
+        assert!(split_address("board").is_err());
+        assert!(split_address("board:gdb").is_err());
+    }
+
+    #[test]
+    fn probes_every_target_when_asked() {
+        let checks = json!({"checkRemoteReachable": true, "timeout": 3});
+        let fake = probe(&[("board", 1234), ("fe80::1", 1235)]);
+        run(&checks, &["board:1234", "[fe80::1]:1235"], &fake).unwrap();
+        assert_eq!(
+            *fake.probed.borrow(),
+            [("board".to_string(), 1234, 3), ("fe80::1".to_string(), 1235, 3)]
+        );
+
+        let fake = probe(&[]);
+        assert_eq!(
+            run(&checks, &["board:1234"], &fake).unwrap_err(),
+            "nothing is listening at board:1234 (checked by `preflight`): Connection refused; \
+             is the debug server running?"
+        );
+
+        let fake = probe(&[]);
+        run(&json!({}), &["board:1234"], &fake).unwrap();
+        assert!(fake.probed.borrow().is_empty());
+    }
+}
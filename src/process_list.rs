@@ -0,0 +1,121 @@
+//! Finding processes on the target by name
+
+use serde_json::Value;
+use zed_extension_api::{Result, process::Command};
+
+use crate::host;
+
+/// A process reported by a `ProcessListProvider`
+pub(crate) struct ProcessInfo {
+    pub(crate) pid: u32,
+    pub(crate) name: String,
+}
+
+/// Lists the processes on whatever the session debugs
+pub(crate) trait ProcessListProvider {
+    fn list_processes(&self, config: &Value) -> Result<Vec<ProcessInfo>>;
+}
+
+/// Lists processes with the `processListCommand` shell command, which prints
+/// one `PID NAME` line per process (e.g. `ps -eo pid,comm`)
+pub(crate) struct ShellCommandProcessListProvider;
+
+impl ProcessListProvider for ShellCommandProcessListProvider {
+    fn list_processes(&self, config: &Value) -> Result<Vec<ProcessInfo>> {
+        let command = config
+            .get("processListCommand")
+            .and_then(|v| v.as_str())
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| {
+                "`processListCommand` is needed to find a process by name (e.g. \"ps -eo pid,comm\")"
+                    .to_string()
+            })?;
+        let output = host::run(
+            Command::new("sh").args(["-c", command]),
+            &format!("`{}`", command),
+        )?;
+
+        // Lines that don't start with a pid, like a header, are skipped
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (pid, name) = line.trim().split_once(char::is_whitespace)?;
+                Some(ProcessInfo {
+                    pid: pid.parse().ok()?,
+                    name: name.trim().to_string(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// The pid of the one process called `name`, matching either the full
+/// name or its last path component
+pub(crate) fn find_pid(
+    provider: &dyn ProcessListProvider,
+    config: &Value,
+    name: &str,
+) -> Result<u32> {
+    let matches: Vec<ProcessInfo> = provider
+        .list_processes(config)?
+        .into_iter()
+        .filter(|p| p.name == name || p.name.rsplit('/').next() == Some(name))
+        .collect();
+    match matches.as_slice() {
+        [process] => Ok(process.pid),
+        [] => Err(format!("no process named {:?} is running", name)),
+        _ => Err(format!(
+            "several processes are named {:?} (pids {}); use a pid instead",
+            name,
+            matches
+                .iter()
+                .map(|p| p.pid.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Reports the same processes whatever the config
+    struct FakeProcessList(&'static [(u32, &'static str)]);
+
+    impl ProcessListProvider for FakeProcessList {
+        fn list_processes(&self, _config: &Value) -> Result<Vec<ProcessInfo>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|&(pid, name)| ProcessInfo { pid, name: name.to_string() })
+                .collect())
+        }
+    }
+
+    const PROCESSES: FakeProcessList =
+        FakeProcessList(&[(1, "/sbin/init"), (412, "/usr/bin/server"), (977, "worker"), (978, "worker")]);
+
+    #[test]
+    fn one_process_by_name_or_basename() {
+        assert_eq!(find_pid(&PROCESSES, &json!({}), "server"), Ok(412));
+        assert_eq!(find_pid(&PROCESSES, &json!({}), "/usr/bin/server"), Ok(412));
+    }
+
+    #[test]
+    fn no_process_is_named_in_the_error() {
+        assert_eq!(
+            find_pid(&PROCESSES, &json!({}), "client"),
+            Err("no process named \"client\" is running".to_string())
+        );
+    }
+
+    #[test]
+    fn several_processes_are_ambiguous() {
+        assert_eq!(
+            find_pid(&PROCESSES, &json!({}), "worker"),
+            Err("several processes are named \"worker\" (pids 977, 978); use a pid instead".to_string())
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Turning Zed's adapter-agnostic debug configs into lldb-remote scenarios
+
+use serde_json::{Map, Value, json};
+use crate::cargo;
+use zed_extension_api::{
+    BuildTaskDefinition, BuildTaskDefinitionTemplatePayload, DebugConfig, DebugRequest,
+    DebugScenario, Result, TaskTemplate,
+};
+
+/// Placeholder address matching the README's `lldb-server gdbserver :2345` example
+const PLACEHOLDER_TARGET: &str = "tcp://127.0.0.1:2345";
+
+/// Placeholder local binary for attach skeletons
+const PLACEHOLDER_PROGRAM: &str = "${ZED_WORKTREE_ROOT}/build/your-application";
+
+/// Build a debug.json-style scenario from a new-session config
+pub(crate) fn from_debug_config(config: DebugConfig) -> Result<DebugScenario> {
+    let mut cfg = Map::new();
+    let mut program_name = None;
+
+    match &config.request {
+        DebugRequest::Launch(launch) => {
+            cfg.insert("request".into(), json!("launch"));
+            cfg.insert("program".into(), json!(launch.program));
+            if !launch.args.is_empty() {
+                cfg.insert("args".into(), json!(launch.args));
+            }
+            if let Some(cwd) = &launch.cwd {
+                cfg.insert("cwd".into(), json!(cwd));
+            }
+            if !launch.envs.is_empty() {
+                let env: Map<String, Value> = launch
+                    .envs
+                    .iter()
+                    .map(|(k, v)| (k.clone(), json!(v)))
+                    .collect();
+                cfg.insert("env".into(), Value::Object(env));
+            }
+            program_name = Some(basename(&launch.program).to_string());
+        }
+        DebugRequest::Attach(attach) => {
+            cfg.insert("request".into(), json!("attach"));
+            cfg.insert("target".into(), json!(PLACEHOLDER_TARGET));
+            cfg.insert("program".into(), json!(PLACEHOLDER_PROGRAM));
+            if let Some(pid) = attach.process_id {
+                cfg.insert("pid".into(), json!(pid));
+            }
+            // Remote sources almost always live under a different root
+            cfg.insert(
+                "pathMappings".into(),
+                json!([{
+                    "localRoot": "${ZED_WORKTREE_ROOT}",
+                    "remoteRoot": "/remote/build/path"
+                }]),
+            );
+        }
+    }
+
+    if let Some(stop_on_entry) = config.stop_on_entry {
+        cfg.insert("stopOnEntry".into(), json!(stop_on_entry));
+    }
+
+    // Keep Zed's label if it has one, otherwise describe what is being debugged
+    let label = if !config.label.is_empty() {
+        config.label.clone()
+    } else {
+        match program_name {
+            Some(name) => format!("Launch {}", name),
+            None => format!("Attach {}", PLACEHOLDER_TARGET),
+        }
+    };
+
+    Ok(DebugScenario {
+        label,
+        adapter: config.adapter,
+        build: None,
+        config: Value::Object(cfg).to_string(),
+        tcp_connection: None,
+    })
+}
+
+/// First phase of the cargo locator: wrap a runnable's cargo command in a build
+/// step whose executable is located afterwards by `cargo::locate_program`
+pub(crate) fn from_cargo_task(
+    locator_name: String,
+    task: TaskTemplate,
+    resolved_label: String,
+    adapter: String,
+) -> Option<DebugScenario> {
+    let build = cargo::locator_build_task(&task)?;
+    Some(DebugScenario {
+        label: resolved_label,
+        adapter,
+        build: Some(BuildTaskDefinition::Template(
+            BuildTaskDefinitionTemplatePayload {
+                locator_name: Some(locator_name),
+                template: build,
+            },
+        )),
+        // Filled in from the located program via `from_debug_config`
+        config: "null".to_string(),
+        tcp_connection: None,
+    })
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
@@ -0,0 +1,788 @@
+//! Running a local debug server (qemu-user, OpenOCD, gdbserver) for the lifetime of the session
+//!
+//! Extensions can only run commands to completion, so the server is started by a
+//! small shell wrapper around the adapter: it starts the server, waits for it to be
+//! ready, runs lldb-dap, and kills the server when lldb-dap exits.
+
+use serde_json::Value;
+use zed_extension_api::{self as zed, Result};
+
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables, host, resolve_worktree_path};
+
+/// Seconds to wait for the server when `readyTimeout` is not set
+const DEFAULT_READY_TIMEOUT_SECS: f64 = 10.0;
+
+/// Seconds to wait for valgrind, which starts much slower than a plain server
+const VALGRIND_READY_TIMEOUT_SECS: f64 = 60.0;
+
+/// Where servers running on this machine listen
+const LOCALHOST: &str = "127.0.0.1";
+
+/// Seconds to wait for an Android device, which also covers pushing lldb-server
+const ANDROID_READY_TIMEOUT_SECS: f64 = 30.0;
+
+/// Where lldb-server is pushed on an Android device
+const ANDROID_TMP_DIR: &str = "/data/local/tmp";
+
+/// sed script printing the PID from valgrind's `==PID== ... vgdb --pid=PID` line
+const VGDB_PID_SED: &str = r"s/^==\([0-9][0-9]*\)==.*vgdb.*--pid=.*/\1/p";
+
+/// A parsed `server` block
+pub(crate) struct Server {
+    command: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    env: Vec<(String, String)>,
+    ready_pattern: Option<String>,
+    ready_timeout_secs: f64,
+    port: Option<u16>,
+    /// Wait for the port to be listening without connecting to it, for stubs
+    /// that only take a single connection
+    wait_for_listen: bool,
+    /// Where `set_program` puts the program in `args`, and the config block
+    /// that needs it, for servers that run the program themselves
+    program_slot: Option<(usize, &'static str)>,
+    /// Host the server is reached at
+    host: String,
+    /// LLDB platform to select before creating the target
+    platform: Option<&'static str>,
+    /// Command words that run the server as root, from `elevate`
+    elevate: Vec<String>,
+}
+
+/// Parse the `server` block, expanding variables and `${serverPort}`
+pub(crate) fn parse(server: &Value, vars: &Variables, worktree_root: &str) -> Result<Server> {
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Err("`server` is not supported on Windows".to_string());
+    }
+
+    let port = match server.get("port") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) if s == "auto" => Some(auto_port()?),
+        Some(v) => Some(
+            v.as_u64()
+                .and_then(|p| u16::try_from(p).ok())
+                .filter(|p| *p > 0)
+                .ok_or_else(|| {
+                    ConfigError::new("server.port", "not a port number")
+                        .value(v)
+                        .hint("expected 1 to 65535, or \"auto\"")
+                })?,
+        ),
+    };
+    let expand = |s: &str| {
+        let s = expand_variables(s, vars);
+        match port {
+            Some(port) => s.replace("${serverPort}", &port.to_string()),
+            None => s,
+        }
+    };
+    let string = |key: &str| -> Result<Option<String>> {
+        match server.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(expand(s))),
+            Some(v) => Err(ConfigError::new(format!("server.{}", key), "not a string").value(v).into()),
+        }
+    };
+
+    let command = string("command")?
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| ConfigError::new("server.command", "is missing"))?;
+    let args = match server.get("args") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(args)) => args
+            .iter()
+            .map(|a| {
+                a.as_str()
+                    .map(expand)
+                    .ok_or_else(|| ConfigError::new("server.args", "has an entry that is not a string")
+                        .value(a)
+                        .to_string())
+            })
+            .collect::<Result<_>>()?,
+        Some(v) => return Err(ConfigError::new("server.args", "not an array").value(v).into()),
+    };
+    let cwd = string("cwd")?.map(|dir| resolve_worktree_path(&dir, worktree_root));
+    let env = match server.get("env") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Object(env)) => env
+            .iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k.clone(), expand(s)),
+                v => (k.clone(), v.to_string()),
+            })
+            .collect(),
+        Some(v) => return Err(ConfigError::new("server.env", "not an object").value(v).into()),
+    };
+    let ready_pattern = string("readyPattern")?;
+    let ready_timeout_secs = match server.get("readyTimeout") {
+        None | Some(Value::Null) => DEFAULT_READY_TIMEOUT_SECS,
+        Some(v) => v.as_f64().filter(|t| *t > 0.0).ok_or_else(|| {
+            ConfigError::new("server.readyTimeout", "not a number of seconds").value(v)
+        })?,
+    };
+
+    Ok(Server {
+        command,
+        args,
+        cwd,
+        env,
+        ready_pattern,
+        ready_timeout_secs,
+        port,
+        wait_for_listen: false,
+        program_slot: None,
+        host: LOCALHOST.to_string(),
+        platform: None,
+        elevate: Vec::new(),
+    })
+}
+
+/// Parse the `qemu` block into a server running qemu-user with its gdb stub on
+/// a fresh port. The program is added with `Server::set_program` once known.
+pub(crate) fn qemu(qemu: &Value, vars: &Variables) -> Result<Server> {
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Err("`qemu` is not supported on Windows".to_string());
+    }
+
+    let string = |key: &str| -> Result<Option<String>> {
+        match qemu.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(expand_variables(s, vars))),
+            Some(v) => Err(ConfigError::new(format!("qemu.{}", key), "not a string").value(v).into()),
+        }
+    };
+
+    // qemu-user binaries are named after the architecture, the first part of the triple
+    let emulator = match string("emulator")? {
+        Some(emulator) => emulator,
+        None => match vars.target_triple.as_deref().and_then(|t| t.split('-').next()) {
+            Some(arch) => format!("qemu-{}", arch),
+            None => {
+                return Err(ConfigError::new("qemu.emulator", "is missing")
+                    .hint("expected e.g. \"qemu-aarch64\"; it can only be inferred from `cargoTarget`")
+                    .into());
+            }
+        },
+    };
+    let program_args = match qemu.get("args") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(args)) => args
+            .iter()
+            .map(|a| {
+                a.as_str()
+                    .map(|a| expand_variables(a, vars))
+                    .ok_or_else(|| ConfigError::new("qemu.args", "has an entry that is not a string")
+                        .value(a)
+                        .to_string())
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Some(v) => return Err(ConfigError::new("qemu.args", "not an array").value(v).into()),
+    };
+    let env = match string("sysroot")? {
+        Some(sysroot) => vec![("QEMU_LD_PREFIX".to_string(), sysroot)],
+        None => Vec::new(),
+    };
+
+    let port = auto_port()?;
+    let mut args = vec!["-g".to_string(), port.to_string()];
+    args.extend(program_args);
+    Ok(Server {
+        command: emulator,
+        args,
+        cwd: None,
+        env,
+        ready_pattern: None,
+        ready_timeout_secs: DEFAULT_READY_TIMEOUT_SECS,
+        port: Some(port),
+        wait_for_listen: true,
+        // After `-g PORT`
+        program_slot: Some((2, "qemu")),
+        host: LOCALHOST.to_string(),
+        platform: None,
+        elevate: Vec::new(),
+    })
+}
+
+/// Parse the `valgrind` block into a server running the program under
+/// valgrind, with `vgdb` relaying its gdb server to a fresh TCP port.
+/// The program is added with `Server::set_program` once known.
+pub(crate) fn valgrind(valgrind: &Value, vars: &Variables) -> Result<Server> {
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Err("`valgrind` is not supported on Windows".to_string());
+    }
+
+    let list = |key: &str| -> Result<Vec<String>> {
+        match valgrind.get(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|a| {
+                    a.as_str()
+                        .map(|a| expand_variables(a, vars))
+                        .ok_or_else(|| ConfigError::new(format!("valgrind.{}", key), "has an entry that is not a string")
+                            .value(a)
+                            .to_string())
+                })
+                .collect(),
+            Some(v) => Err(ConfigError::new(format!("valgrind.{}", key), "not an array").value(v).into()),
+        }
+    };
+    let command = match valgrind.get("command") {
+        None | Some(Value::Null) => "valgrind".to_string(),
+        Some(Value::String(s)) if !s.is_empty() => expand_variables(s, vars),
+        Some(v) => return Err(ConfigError::new("valgrind.command", "not a string").value(v).into()),
+    };
+    let options = list("options")?;
+    let program_args = list("args")?;
+
+    // valgrind stops before the first instruction (`--vgdb-error=0`) and prints
+    // `==PID== ... vgdb --pid=PID`; vgdb then serves that process over TCP.
+    // lldb can't talk to vgdb over a pipe like gdb's `target remote | vgdb`.
+    let port = auto_port()?;
+    let mut runner = format!("{} --vgdb=yes --vgdb-error=0", shell_quote(&command));
+    for option in &options {
+        runner.push(' ');
+        runner.push_str(&shell_quote(option));
+    }
+    let script = format!(
+        r#"vglog=$(mktemp "${{TMPDIR:-/tmp}}/lldb-remote-valgrind.XXXXXX") || exit 1
+{runner} "$@" >"$vglog" 2>&1 </dev/null &
+vg=$!
+relay=
+trap 'kill "$vg" $relay 2>/dev/null; cat "$vglog"; rm -f "$vglog"' EXIT
+trap 'exit 143' TERM
+pid=
+until [ -n "$pid" ]; do
+  kill -0 "$vg" 2>/dev/null || break
+  sleep 0.1
+  pid=$(sed -n '{pid_sed}' "$vglog" | head -n 1)
+done
+[ -n "$pid" ] || {{ echo "lldb-remote: valgrind exited without reporting a vgdb pid" >&2; exit 1; }}
+vgdb --port={port} --pid="$pid" &
+relay=$!
+wait "$relay""#,
+        runner = runner,
+        pid_sed = VGDB_PID_SED,
+        port = port,
+    );
+
+    let mut args = vec!["-c".to_string(), script, "lldb-remote-valgrind".to_string()];
+    args.extend(program_args);
+    Ok(Server {
+        command: "sh".to_string(),
+        args,
+        cwd: None,
+        env: Vec::new(),
+        ready_pattern: None,
+        // valgrind is slow to start large programs
+        ready_timeout_secs: VALGRIND_READY_TIMEOUT_SECS,
+        port: Some(port),
+        wait_for_listen: true,
+        // After the script and its `$0`
+        program_slot: Some((3, "valgrind")),
+        host: LOCALHOST.to_string(),
+        platform: None,
+        elevate: Vec::new(),
+    })
+}
+
+/// Parse the `rr` block into a server replaying the trace with rr's gdb server
+pub(crate) fn rr(rr: &Value, vars: &Variables) -> Result<Server> {
+    if zed::current_platform().0 != zed::Os::Linux {
+        return Err("`rr` is only supported on Linux".to_string());
+    }
+
+    let trace = rr_trace(rr, vars)?;
+    let port = match rr.get("port") {
+        None | Some(Value::Null) => auto_port()?,
+        Some(v) => v
+            .as_u64()
+            .and_then(|p| u16::try_from(p).ok())
+            .filter(|p| *p > 0)
+            .ok_or_else(|| ConfigError::new("rr.port", "not a port number")
+                .value(v)
+                .hint("expected 1 to 65535"))?,
+    };
+
+    // `-k` keeps the server up when LLDB disconnects, so the session can reconnect
+    Ok(Server {
+        command: "rr".to_string(),
+        args: vec![
+            "replay".to_string(),
+            "-s".to_string(),
+            port.to_string(),
+            "-k".to_string(),
+            trace,
+        ],
+        cwd: None,
+        env: Vec::new(),
+        ready_pattern: None,
+        ready_timeout_secs: DEFAULT_READY_TIMEOUT_SECS,
+        port: Some(port),
+        wait_for_listen: true,
+        program_slot: None,
+        host: LOCALHOST.to_string(),
+        platform: None,
+        elevate: Vec::new(),
+    })
+}
+
+/// The binary recorded in the `rr` block's trace, from the first process `rr ps` lists
+pub(crate) fn rr_program(rr: &Value, vars: &Variables) -> Result<String> {
+    let trace = rr_trace(rr, vars)?;
+    let output = host::run(
+        zed::process::Command::new("rr").args(["ps", trace.as_str()]),
+        &format!("`rr ps {}`", trace),
+    )?;
+
+    // `PID PPID EXIT CMD...`, tab-separated after a header line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let program = stdout
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split('\t').nth(3))
+        .and_then(|cmd| cmd.split_whitespace().next())
+        .ok_or_else(|| format!("`rr ps {}` listed no processes", trace))?;
+    if !program.starts_with('/') {
+        return Err(format!(
+            "the trace recorded {:?} relative to a directory it doesn't name; set `program`",
+            program
+        ));
+    }
+    Ok(program.to_string())
+}
+
+fn rr_trace(rr: &Value, vars: &Variables) -> Result<String> {
+    match rr.get("trace") {
+        Some(Value::String(trace)) if !trace.is_empty() => Ok(expand_variables(trace, vars)),
+        Some(v) => Err(ConfigError::new("rr.trace", "not a trace directory").value(v).into()),
+        None => Err(ConfigError::new("rr.trace", "is missing").into()),
+    }
+}
+
+/// Parse the `docker` block into a server running lldb-server inside the
+/// container, reached through the port it publishes as `docker.port`, or else
+/// at the container's address
+pub(crate) fn docker(docker: &Value, vars: &Variables) -> Result<Server> {
+    let os = zed::current_platform().0;
+    if os == zed::Os::Windows {
+        return Err("`docker` is not supported on Windows".to_string());
+    }
+
+    let container = match docker.get("container") {
+        Some(Value::String(c)) if !c.is_empty() => expand_variables(c, vars),
+        Some(v) => return Err(ConfigError::new("docker.container", "not a name or id").value(v).into()),
+        None => return Err(ConfigError::new("docker.container", "is missing").into()),
+    };
+    let lldb_server = match docker.get("lldbServer") {
+        None | Some(Value::Null) => "lldb-server".to_string(),
+        Some(Value::String(s)) if !s.is_empty() => expand_variables(s, vars),
+        Some(v) => return Err(ConfigError::new("docker.lldbServer", "not a command").value(v).into()),
+    };
+    // Attach to a running process, or start the program under lldb-server
+    let debuggee = match (docker.get("pid"), docker.get("program")) {
+        (Some(_), Some(_)) => {
+            return Err("`docker.pid` and `docker.program` cannot both be set".to_string());
+        }
+        (Some(pid), None) => {
+            let pid = pid
+                .as_u64()
+                .or_else(|| pid.as_str().and_then(|p| p.parse().ok()))
+                .ok_or_else(|| ConfigError::new("docker.pid", "not a process id").value(pid))?;
+            format!("--attach {}", pid)
+        }
+        (None, Some(Value::String(program))) if !program.is_empty() => {
+            shell_quote(&expand_variables(program, vars))
+        }
+        (None, Some(v)) => {
+            return Err(ConfigError::new("docker.program", "not a path in the container").value(v).into());
+        }
+        (None, None) => return Err("`docker` needs either `pid` or `program`".to_string()),
+    };
+
+    let (port, host, host_port) = match docker.get("port") {
+        // A published port is reached on this machine, wherever the container runs
+        Some(v) if !v.is_null() => {
+            let port = v
+                .as_u64()
+                .and_then(|p| u16::try_from(p).ok())
+                .filter(|p| *p > 0)
+                .ok_or_else(|| ConfigError::new("docker.port", "not a port number")
+                    .value(v)
+                    .hint("expected 1 to 65535"))?;
+            let output = host::run(
+                zed::process::Command::new("docker").args(["port", container.as_str(), &format!("{}/tcp", port)]),
+                &format!("`docker port {} {}`", container, port),
+            )?;
+            let host_port = published_port(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+                format!("container {} doesn't publish port {} (`docker run -p {}:{}`)", container, port, port, port)
+            })?;
+            (port, LOCALHOST.to_string(), host_port)
+        }
+        // Otherwise connect to the container directly. A container on the host
+        // network has no address of its own.
+        _ => {
+            let output = host::run(
+                zed::process::Command::new("docker").args([
+                    "inspect",
+                    "--format",
+                    "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}",
+                    container.as_str(),
+                ]),
+                &format!("`docker inspect {}`", container),
+            )?;
+            let address = String::from_utf8_lossy(&output.stdout).split_whitespace().next().map(str::to_string);
+            // Docker Desktop runs containers in a VM whose addresses aren't routed to the host
+            if address.is_some() && os != zed::Os::Linux {
+                return Err(format!(
+                    "container addresses can't be reached from this host; publish a port from {} (`docker run -p 2345:2345`) and set `docker.port`",
+                    container
+                ));
+            }
+            let port = auto_port()?;
+            (port, address.unwrap_or_else(|| LOCALHOST.to_string()), port)
+        }
+    };
+
+    // Killing `docker exec` leaves its process running in the container, so
+    // lldb-server records its pid there and is killed by that on the way out
+    let pid_file = format!("/tmp/lldb-remote-{}.pid", port);
+    let exec = |script: &str| {
+        format!(
+            "docker exec {} sh -c {}",
+            shell_quote(&container),
+            shell_quote(script)
+        )
+    };
+    let script = format!(
+        "{start} &\nexec_pid=$!\ntrap '{stop} >/dev/null 2>&1' EXIT\ntrap 'exit 143' TERM\nwait \"$exec_pid\"",
+        start = exec(&format!(
+            "echo $$ > {pid_file}; exec {server} gdbserver 0.0.0.0:{port} {debuggee}",
+            pid_file = pid_file,
+            server = shell_quote(&lldb_server),
+            port = port,
+            debuggee = debuggee,
+        )),
+        stop = exec(&format!(
+            "kill $(cat {pid_file}) 2>/dev/null; rm -f {pid_file}",
+            pid_file = pid_file
+        ))
+        .replace('\'', r"'\''"),
+    );
+
+    Ok(Server {
+        command: "sh".to_string(),
+        args: vec!["-c".to_string(), script],
+        cwd: None,
+        env: Vec::new(),
+        ready_pattern: Some("Listening to port".to_string()),
+        ready_timeout_secs: DEFAULT_READY_TIMEOUT_SECS,
+        port: Some(host_port),
+        wait_for_listen: false,
+        program_slot: None,
+        host,
+        platform: None,
+        elevate: Vec::new(),
+    })
+}
+
+/// The host port in `docker port` output such as `0.0.0.0:32768` then `[::]:32768`
+fn published_port(output: &str) -> Option<u16> {
+    output.lines().find_map(|line| line.trim().rsplit_once(':')?.1.parse().ok())
+}
+
+/// Parse the `android` block into a server that pushes the NDK's lldb-server
+/// to the device, runs it there attached to the app, and forwards it to a
+/// local port with `adb forward`
+pub(crate) fn android(android: &Value, vars: &Variables) -> Result<Server> {
+    if zed::current_platform().0 == zed::Os::Windows {
+        return Err("`android` is not supported on Windows".to_string());
+    }
+
+    let string = |key: &str| -> Result<Option<String>> {
+        match android.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) if !s.is_empty() => Ok(Some(expand_variables(s, vars))),
+            Some(v) => Err(ConfigError::new(format!("android.{}", key), "not a string").value(v).into()),
+        }
+    };
+
+    let package_or_pid = match android.get("packageOrPid") {
+        Some(Value::Number(pid)) if pid.is_u64() => pid.to_string(),
+        Some(Value::String(s)) if !s.is_empty() => expand_variables(s, vars),
+        Some(v) => {
+            return Err(ConfigError::new("android.packageOrPid", "not a package name or a process id")
+                .value(v)
+                .into());
+        }
+        None => return Err(ConfigError::new("android.packageOrPid", "is missing").into()),
+    };
+    let abi = string("abi")?.unwrap_or_else(|| "arm64-v8a".to_string());
+    let arch = match abi.as_str() {
+        "arm64-v8a" => "aarch64",
+        "armeabi-v7a" => "arm",
+        "x86" => "i386",
+        "x86_64" => "x86_64",
+        _ => {
+            return Err(ConfigError::new("android.abi", "unknown ABI")
+                .value(format!("{:?}", abi))
+                .hint("expected arm64-v8a, armeabi-v7a, x86 or x86_64")
+                .into());
+        }
+    };
+    let ndk = string("ndkPath")?
+        .unwrap_or_else(|| expand_variables("${env:ANDROID_NDK_HOME}", vars));
+    if ndk.contains("${env:") {
+        return Err(ConfigError::new("android.ndkPath", "is missing")
+            .hint("expected the NDK directory, or ANDROID_NDK_HOME set")
+            .into());
+    }
+    let adb = match string("serial")? {
+        Some(serial) => format!("command adb -s {} \"$@\"", shell_quote(&serial)),
+        None => "command adb \"$@\"".to_string(),
+    };
+
+    let port = auto_port()?;
+    // A package is debugged as its own user with run-as, a bare pid needs root
+    let find_pid = match package_or_pid.parse::<u32>() {
+        Ok(pid) => format!("pid={}\nrun=\"su 0\"\ndir={}", pid, ANDROID_TMP_DIR),
+        Err(_) if !package_or_pid
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_') =>
+        {
+            return Err(ConfigError::new("android.packageOrPid", "not a package name or a process id")
+                .value(format!("{:?}", package_or_pid))
+                .into());
+        }
+        Err(_) => format!(
+            r#"pid=$(adb shell pidof {package} | tr -d '\r')
+pid=${{pid%% *}}
+[ -n "$pid" ] || {{ echo "lldb-remote: {package} is not running on the device"; exit 1; }}
+step adb shell run-as {package} cp {tmp}/lldb-server ./lldb-server
+run="run-as {package}"
+dir=."#,
+            package = package_or_pid,
+            tmp = ANDROID_TMP_DIR,
+        ),
+    };
+
+    // Every step's adb output ends up in the server log, which is shown if
+    // startup fails. lldb-server listens on an abstract socket, which apps
+    // without the INTERNET permission can still open.
+    let script = format!(
+        r#"adb() {{ {adb}; }}
+step() {{
+  out=$("$@" 2>&1) || {{ printf 'lldb-remote: `%s` failed:\n%s\n' "$*" "$out"; exit 1; }}
+  [ -z "$out" ] || printf '%s\n' "$out"
+}}
+server=
+for s in {ndk}/toolchains/llvm/prebuilt/*/lib/clang/*/lib/linux/{arch}/lldb-server {ndk}/toolchains/llvm/prebuilt/*/lib64/clang/*/lib/linux/{arch}/lldb-server; do
+  [ -f "$s" ] && server=$s
+done
+[ -n "$server" ] || {{ echo "lldb-remote: the NDK in "{ndk}" has no lldb-server for {abi}"; exit 1; }}
+step adb push "$server" {tmp}/lldb-server
+step adb shell chmod 755 {tmp}/lldb-server
+{find_pid}
+socket=lldb-remote-{port}
+start="echo \$\$ > $dir/$socket.pid; exec $dir/lldb-server gdbserver unix-abstract:///$socket --attach $pid"
+stop="kill \$(cat $dir/$socket.pid) 2>/dev/null; rm -f $dir/$socket.pid"
+step adb forward tcp:{port} localabstract:/$socket
+cleanup() {{
+  adb shell "$run sh -c '$stop'" >/dev/null 2>&1
+  adb forward --remove tcp:{port} >/dev/null 2>&1
+}}
+trap cleanup EXIT
+trap 'exit 143' TERM
+adb shell "$run sh -c '$start'" &
+shell=$!
+until adb shell cat /proc/net/unix 2>/dev/null | grep -q "@/$socket"; do
+  kill -0 "$shell" 2>/dev/null || exit 1
+  sleep 0.2
+done
+echo "lldb-remote: lldb-server is listening on the device"
+wait "$shell""#,
+        adb = adb,
+        ndk = shell_quote(&ndk),
+        arch = arch,
+        abi = abi,
+        tmp = ANDROID_TMP_DIR,
+        find_pid = find_pid,
+        port = port,
+    );
+
+    Ok(Server {
+        command: "sh".to_string(),
+        args: vec!["-c".to_string(), script],
+        cwd: None,
+        env: Vec::new(),
+        ready_pattern: Some("^lldb-remote: lldb-server is listening".to_string()),
+        ready_timeout_secs: ANDROID_READY_TIMEOUT_SECS,
+        port: Some(port),
+        wait_for_listen: false,
+        program_slot: None,
+        host: LOCALHOST.to_string(),
+        platform: Some("remote-android"),
+        elevate: Vec::new(),
+    })
+}
+
+/// A port for `"port": "auto"`, one Zed finds free on this machine
+fn auto_port() -> Result<u16> {
+    Ok(zed::resolve_tcp_template(zed::TcpArgumentsTemplate {
+        host: None,
+        port: None,
+        timeout: None,
+    })?
+    .port)
+}
+
+impl Server {
+    /// The server's port, which `${serverPort}` expands to
+    pub(crate) fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// `HOST:PORT` of the server, if it has a port
+    pub(crate) fn address(&self) -> Option<String> {
+        self.port.map(|port| format!("{}:{}", self.host, port))
+    }
+
+    /// LLDB platform the debuggee runs on, if not the host's
+    pub(crate) fn platform(&self) -> Option<&'static str> {
+        self.platform
+    }
+
+    /// Config block of a server that runs the program itself, before its program is set
+    pub(crate) fn needs_program(&self) -> Option<&'static str> {
+        self.program_slot.map(|(_, block)| block)
+    }
+
+    /// Have a server that runs the program itself run `program`, before its arguments
+    pub(crate) fn set_program(&mut self, program: &str) {
+        if let Some((index, _)) = self.program_slot.take() {
+            self.args.insert(index, program.to_string());
+        }
+    }
+
+    /// Run the server through `prefix`, e.g. `sudo -A`
+    pub(crate) fn elevate(&mut self, prefix: &[String]) {
+        self.elevate = prefix.to_vec();
+    }
+
+    /// Arguments for `sh` that start the server and then run `adapter` with
+    /// `adapter_args` in the foreground
+    pub(crate) fn wrap(&self, adapter: &str, adapter_args: &[String]) -> Vec<String> {
+        let mut server_cmd = String::new();
+        if let Some(cwd) = &self.cwd {
+            server_cmd.push_str(&format!("cd {} && ", shell_quote(cwd)));
+        }
+        server_cmd.push_str("exec ");
+        for word in &self.elevate {
+            server_cmd.push_str(&shell_quote(word));
+            server_cmd.push(' ');
+        }
+        if !self.env.is_empty() {
+            server_cmd.push_str("env ");
+            for (k, v) in &self.env {
+                server_cmd.push_str(&shell_quote(&format!("{}={}", k, v)));
+                server_cmd.push(' ');
+            }
+        }
+        server_cmd.push_str(&shell_quote(&self.command));
+        for arg in &self.args {
+            server_cmd.push(' ');
+            server_cmd.push_str(&shell_quote(arg));
+        }
+
+        // Ready once the pattern shows up in the output, or else once the port accepts connections
+        let ready_check = match (&self.ready_pattern, self.port) {
+            (Some(pattern), _) => format!("grep -Eq -- {} \"$log\"", shell_quote(pattern)),
+            (None, Some(port)) if self.wait_for_listen => format!(
+                "{{ ss -ltn 2>/dev/null || netstat -ltn 2>/dev/null; }} | grep -q '[:.]{} '",
+                port
+            ),
+            (None, Some(port)) => format!("nc -z 127.0.0.1 {} >/dev/null 2>&1", port),
+            (None, None) => "true".to_string(),
+        };
+        let ticks = (self.ready_timeout_secs * 10.0).ceil() as u64;
+
+        // The server's output must stay off stdout, which carries the DAP protocol.
+        // The watchdog stops the server even if the wrapper is killed without
+        // running its trap. The output is kept if the server fails.
+        let script = format!(
+            r#"log=$(mktemp "${{TMPDIR:-/tmp}}/lldb-remote-server.XXXXXX") || exit 1
+( {server_cmd} ) >"$log" 2>&1 </dev/null &
+server=$!
+wrapper=$$
+( while kill -0 "$wrapper" 2>/dev/null; do sleep 1; done; kill "$server" 2>/dev/null ) >/dev/null 2>&1 </dev/null &
+name={name}
+fail() {{ echo "lldb-remote: $1; its output, kept in $log, was:" >&2; cat "$log" >&2; kill "$server" 2>/dev/null; exit 1; }}
+i=0
+until {ready_check}; do
+  kill -0 "$server" 2>/dev/null || fail "debug server $name exited during startup"
+  i=$((i + 1))
+  [ "$i" -ge {ticks} ] && fail "debug server $name was not ready after {timeout}s"
+  sleep 0.1
+done
+cleanup() {{
+  if kill "$server" 2>/dev/null || wait "$server"; then
+    rm -f "$log"
+  else
+    echo "lldb-remote: debug server $name failed during the session; its output is in $log" >&2
+  fi
+}}
+trap cleanup EXIT
+trap 'exit 129' HUP
+trap 'exit 130' INT
+trap 'exit 143' TERM
+"$@""#,
+            server_cmd = server_cmd,
+            ready_check = ready_check,
+            name = shell_quote(&self.command),
+            ticks = ticks,
+            timeout = self.ready_timeout_secs,
+        );
+
+        let mut arguments = vec![
+            "-c".to_string(),
+            script,
+            "lldb-remote".to_string(),
+            adapter.to_string(),
+        ];
+        arguments.extend_from_slice(adapter_args);
+        arguments
+    }
+}
+
+/// Quote `s` as a single POSIX shell word
+pub(crate) fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vgdb_pid_sed_prints_the_pid() {
+        let log = "==4242== Memcheck, a memory error detector\n\
+                   ==4242==   /path/to/gdb ./prog\n\
+                   ==4242==   target remote | /usr/bin/vgdb --pid=4242\n";
+        let out = std::process::Command::new("sh")
+            .args(["-c", &format!("printf '%s' \"$1\" | sed -n '{}'", VGDB_PID_SED), "sh", log])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "4242\n");
+    }
+
+    #[test]
+    fn published_port_reads_docker_port_output() {
+        assert_eq!(published_port("0.0.0.0:32768\n[::]:32768\n"), Some(32768));
+        assert_eq!(published_port("[::]:49153\n"), Some(49153));
+        assert_eq!(published_port("[::]:1234\n0.0.0.0:1234\n"), Some(1234));
+        assert_eq!(published_port("0.0.0.0:1234\n[::]:1234\n"), Some(1234));
+        assert_eq!(published_port(""), None);
+    }
+}
@@ -0,0 +1,146 @@
+"""Peripheral registers from a CMSIS-SVD file, for LLDB.
+
+Bundled with the zed-lldb-remote extension and imported when a debug
+configuration sets `svdFile`.
+
+    svd load <file>                 parse the SVD file
+    svd list                        list peripherals
+    svd show <peripheral> [<reg>]   read a peripheral's registers and fields
+"""
+
+import shlex
+import xml.etree.ElementTree as ET
+
+import lldb
+
+_device = None
+
+
+def _int(text, default=None):
+    if text is None:
+        return default
+    text = text.strip().lower()
+    if text.startswith("#"):
+        # Binary with `x` don't-care bits; treat them as zero
+        return int(text[1:].replace("x", "0"), 2)
+    return int(text, 0)
+
+
+class Field:
+    def __init__(self, node):
+        self.name = node.findtext("name")
+        self.description = " ".join((node.findtext("description") or "").split())
+        if node.find("bitRange") is not None:
+            msb, lsb = node.findtext("bitRange").strip("[]").split(":")
+            self.lsb, self.width = int(lsb), int(msb) - int(lsb) + 1
+        elif node.find("lsb") is not None:
+            self.lsb = int(node.findtext("lsb"))
+            self.width = int(node.findtext("msb")) - self.lsb + 1
+        else:
+            self.lsb = int(node.findtext("bitOffset"))
+            self.width = int(node.findtext("bitWidth", "1"))
+
+    def value(self, register_value):
+        return (register_value >> self.lsb) & ((1 << self.width) - 1)
+
+
+class Register:
+    def __init__(self, node, default_size):
+        self.name = node.findtext("name")
+        self.offset = _int(node.findtext("addressOffset"), 0)
+        self.size = _int(node.findtext("size"), default_size)
+        self.fields = [Field(f) for f in node.findall("./fields/field")]
+
+
+class Peripheral:
+    def __init__(self, node, default_size, base=None):
+        self.name = node.findtext("name")
+        self.base_address = _int(node.findtext("baseAddress"), 0)
+        size = _int(node.findtext("size"), default_size)
+        registers = [Register(r, size) for r in node.iter("register")]
+        self.registers = registers or (base.registers if base else [])
+
+
+class Device:
+    def __init__(self, path):
+        root = ET.parse(path).getroot()
+        size = _int(root.findtext("size"), 32)
+        self.name = root.findtext("name")
+        self.peripherals = {}
+        for node in root.findall("./peripherals/peripheral"):
+            base = self.peripherals.get(node.get("derivedFrom", ""))
+            peripheral = Peripheral(node, size, base)
+            self.peripherals[peripheral.name.upper()] = peripheral
+
+
+def _read(process, address, size_bits):
+    error = lldb.SBError()
+    data = process.ReadMemory(address, size_bits // 8, error)
+    if not error.Success():
+        return None
+    return int.from_bytes(data, "little")
+
+
+def svd(debugger, command, result, internal_dict):
+    global _device
+    args = shlex.split(command)
+    if not args:
+        result.SetError("usage: svd load <file> | svd list | svd show <peripheral> [<register>]")
+        return
+
+    if args[0] == "load" and len(args) == 2:
+        try:
+            _device = Device(args[1])
+        except (OSError, ET.ParseError, ValueError) as e:
+            result.SetError("cannot load {}: {}".format(args[1], e))
+            return
+        result.AppendMessage(
+            "svd: loaded {} ({} peripherals)".format(_device.name, len(_device.peripherals))
+        )
+        return
+
+    if _device is None:
+        result.SetError("no SVD file loaded (svd load <file>)")
+        return
+
+    if args[0] == "list":
+        for peripheral in sorted(_device.peripherals.values(), key=lambda p: p.base_address):
+            result.AppendMessage("{:<16} 0x{:08x}".format(peripheral.name, peripheral.base_address))
+        return
+
+    if args[0] == "show" and len(args) in (2, 3):
+        peripheral = _device.peripherals.get(args[1].upper())
+        if peripheral is None:
+            result.SetError("no peripheral named {}".format(args[1]))
+            return
+        registers = peripheral.registers
+        if len(args) == 3:
+            registers = [r for r in registers if r.name.upper() == args[2].upper()]
+            if not registers:
+                result.SetError("{} has no register named {}".format(peripheral.name, args[2]))
+                return
+        process = debugger.GetSelectedTarget().GetProcess()
+        for register in registers:
+            address = peripheral.base_address + register.offset
+            value = _read(process, address, register.size) if process.IsValid() else None
+            shown = "<unreadable>" if value is None else "0x{:0{}x}".format(value, register.size // 4)
+            result.AppendMessage("{}.{} @ 0x{:08x} = {}".format(peripheral.name, register.name, address, shown))
+            if value is None:
+                continue
+            for field in sorted(register.fields, key=lambda f: -f.lsb):
+                result.AppendMessage(
+                    "    {:<20} [{}:{}] = {:#x}  {}".format(
+                        field.name,
+                        field.lsb + field.width - 1,
+                        field.lsb,
+                        field.value(value),
+                        field.description,
+                    )
+                )
+        return
+
+    result.SetError("usage: svd load <file> | svd list | svd show <peripheral> [<register>]")
+
+
+def __lldb_init_module(debugger, internal_dict):
+    debugger.HandleCommand("command script add -o -f {}.svd svd".format(__name__))
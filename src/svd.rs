@@ -0,0 +1,60 @@
+//! Peripheral registers from a CMSIS-SVD file, through a Python plugin loaded into LLDB
+
+use serde_json::Value;
+use zed_extension_api::Result;
+
+use crate::command::quote;
+use crate::error::ConfigError;
+use crate::{Variables, expand_variables, host, resolve_worktree_path};
+
+/// Plugin used unless `svdPlugin` names another one
+const BUNDLED_PLUGIN: &str = include_str!("svd.py");
+
+/// Loads the SVD file with the bundled plugin; `{file}` is the SVD path
+const DEFAULT_LOAD_COMMAND: &str = "svd load {file}";
+
+/// Commands that import the SVD plugin and load `svdFile`, or nothing if it isn't set
+pub(crate) fn commands(cfg_in: &Value, vars: &Variables, worktree_root: &str) -> Result<Vec<String>> {
+    let path = |key: &str| -> Result<Option<String>> {
+        match cfg_in.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(p)) if !p.is_empty() => {
+                let p = resolve_worktree_path(&expand_variables(p, vars), worktree_root);
+                if !host::is_file(&p)? {
+                    return Err(format!("`{}` {} does not exist", key, p));
+                }
+                Ok(Some(p))
+            }
+            Some(v) => Err(ConfigError::new(key, "not a path").value(v).into()),
+        }
+    };
+    let Some(file) = path("svdFile")? else {
+        return Ok(Vec::new());
+    };
+
+    let (plugin, load) = match path("svdPlugin")? {
+        Some(plugin) => {
+            let load = cfg_in
+                .get("svdLoadCommand")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| "`svdPlugin` needs `svdLoadCommand` to know how to load the SVD file".to_string())?;
+            (plugin, load.to_string())
+        }
+        None => (bundled_plugin()?, DEFAULT_LOAD_COMMAND.to_string()),
+    };
+
+    Ok(vec![
+        format!("command script import {}", quote(&plugin)),
+        load.replace("{file}", &quote(&file)),
+    ])
+}
+
+/// Write the bundled plugin into the extension's directory so LLDB can import it
+fn bundled_plugin() -> Result<String> {
+    let dir = std::env::current_dir()
+        .map_err(|e| format!("failed to find the extension directory: {}", e))?;
+    let path = dir.join("svd.py");
+    std::fs::write(&path, BUNDLED_PLUGIN)
+        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,98 @@
+//! Paths of files inside a WSL distribution, as seen from Windows and from Linux
+
+use crate::config::{OneOrMany, RemoteDebugConfig};
+
+/// UNC prefixes Windows uses for WSL distributions, with forward slashes
+const PREFIXES: [&str; 2] = ["//wsl.localhost/", "//wsl$/"];
+
+/// The Windows side of a WSL distribution's filesystem, e.g. `\\wsl$\Ubuntu`
+pub(crate) struct Root {
+    prefix: &'static str,
+    distro: String,
+}
+
+impl Root {
+    /// The Windows path of the Linux path `path`, in the spelling the root was written in
+    pub(crate) fn to_windows(&self, path: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.prefix.replace('/', "\\"),
+            self.distro,
+            path.replace('/', "\\")
+        )
+    }
+}
+
+/// Split `\\wsl$\<distro>\home\me` or `\\wsl.localhost\<distro>\home\me`
+/// into the distribution's root and the Linux path `/home/me`. Other paths
+/// give `None`.
+pub(crate) fn split(path: &str) -> Option<(Root, String)> {
+    let path = path.replace('\\', "/");
+    let prefix = PREFIXES.into_iter().find(|p| {
+        path.get(..p.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(p))
+    })?;
+    let rest = &path[prefix.len()..];
+    let (distro, linux) = match rest.split_once('/') {
+        Some((distro, linux)) => (distro, format!("/{}", linux)),
+        None => (rest, "/".to_string()),
+    };
+    if distro.is_empty() {
+        return None;
+    }
+    Some((
+        Root {
+            prefix,
+            distro: distro.to_string(),
+        },
+        linux,
+    ))
+}
+
+/// The Linux path of a WSL path, or the path unchanged
+pub(crate) fn to_linux(path: &str) -> String {
+    match split(path) {
+        Some((_, linux)) => linux,
+        None => path.to_string(),
+    }
+}
+
+/// The distribution the config points into, from the first WSL path among
+/// `program` and `pathMappings[].localRoot`
+pub(crate) fn detect(flags: &RemoteDebugConfig) -> Option<Root> {
+    let program: Vec<&str> = match &flags.program {
+        Some(OneOrMany::One(program)) => vec![program],
+        Some(OneOrMany::Many(candidates)) => candidates.iter().map(String::as_str).collect(),
+        None => Vec::new(),
+    };
+    let local_roots = flags.path_mappings.iter().flatten().map(|m| m.local_root.as_str());
+    program
+        .into_iter()
+        .chain(local_roots)
+        .find_map(split)
+        .map(|(root, _)| root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wsl_paths_round_trip_in_either_spelling() {
+        for windows in [r"\\wsl$\Ubuntu\home\me\app", r"\\wsl.localhost\Ubuntu-22.04\home\me\app"] {
+            let (root, linux) = split(windows).unwrap();
+            assert_eq!(linux, "/home/me/app");
+            assert_eq!(to_linux(windows), "/home/me/app");
+            assert_eq!(root.to_windows(&linux), windows);
+        }
+        assert_eq!(to_linux("//WSL.LOCALHOST/Debian"), "/");
+    }
+
+    #[test]
+    fn other_paths_pass_through() {
+        for path in ["/home/me/app", r"C:\src\app", r"\\server\share\app", r"\\wsl$\", "app"] {
+            assert!(split(path).is_none(), "{}", path);
+            assert_eq!(to_linux(path), path);
+        }
+    }
+}